@@ -0,0 +1,3 @@
+pub mod sortedcontainer;
+pub mod sortedmap;
+pub mod persistentcontainer;
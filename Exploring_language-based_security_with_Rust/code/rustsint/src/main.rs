@@ -1,9 +1,17 @@
-mod sortedcontainer;
+extern crate rustsint;
 
-use sortedcontainer::SortedContainer;
-use std::io::{self, Write};
+use rustsint::sortedcontainer::{SortedContainer, Balancing, Multiplicity};
+use rustsint::sortedmap::SortedMap;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
+use std::convert::TryFrom;
+use std::env;
+use std::process;
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::fmt;
+use std::ops::Bound;
 
 
 
@@ -12,12 +20,85 @@ enum Command {
     Insert{age: u32, name: String},
     Erase{age: u32, name: String},
     Contains{age: u32, name: String},
+    Range{min_age: u32, max_age: u32},
+    Floor{age: u32},
+    Ceiling{age: u32},
+    Nearest{age: u32},
+    // A set operation, either displayed or (when apply
+    // is true) applied to the records
+    SetOp{op: SetOp, file: String, apply: bool},
+    Sort{order: Order},
+    ByName{name: String},
+    ByAge{age: u32},
+    Undo,
+    Redo,
+    History,
+    Begin,
+    Commit,
+    Rollback,
+    Save{file: String},
+    Load{file: String},
+    Format{format: Format},
+    Dot{file: String},
+    Tree,
     Print,
     Exit,
     Error(String)
 }
 
 
+// The ways the current container can be
+// combined with the records of a file
+#[derive(Debug)]
+enum SetOp {
+    Union,
+    Intersection,
+    Difference,
+    SymmetricDifference,
+    Subset,
+    Disjoint,
+}
+
+
+// The formats in which the results of the commands
+// can be displayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+
+impl Format {
+
+    // The format with the given name
+    fn parse(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Format::Text => "text",
+            Format::Json => "json",
+        }
+    }
+}
+
+
+// The orders in which the records can be listed
+#[derive(Debug)]
+enum Order {
+    Age,
+    Name,
+    Length,
+    Descending,
+}
+
+
 #[derive(Debug)]
 struct Data {
    age: u32,
@@ -25,7 +106,7 @@ struct Data {
 }
 
 
-// This is required in order to implement the trait PartialOrd
+// This is required in order to implement the trait Ord
 impl PartialEq for Data {
     fn eq(&self, other: &Data) -> bool {
         self.age  == other.age &&
@@ -34,18 +115,86 @@ impl PartialEq for Data {
 }
 
 
-// This is required in order to use the sortedcontainer module
+impl Eq for Data {}
+
+
 impl PartialOrd for Data {
     fn partial_cmp(&self,other: &Data) -> Option<Ordering> {
-        Some(
-            self.age.cmp(&other.age).then(
-                self.name.cmp(&other.name)
-            )
+        Some(self.cmp(other))
+    }
+}
+
+
+// This is required in order to use the sortedcontainer module
+// (records are totally ordered, by age first, then by name)
+impl Ord for Data {
+    fn cmp(&self, other: &Data) -> Ordering {
+        self.age.cmp(&other.age).then(
+            self.name.cmp(&other.name)
         )
     }
 }
 
 
+// The key identifying a record: its age and its name.
+// Any type able to provide these two values (e.g. a pair
+// (u32, &str)) can be used to look up a Data in the
+// sortedcontainer, without building a whole Data (and
+// thus without allocating a String)
+trait Key {
+    fn key(&self) -> (u32, &str);
+}
+
+
+impl Key for Data {
+    fn key(&self) -> (u32, &str) {
+        (self.age, &self.name)
+    }
+}
+
+
+impl Key for (u32, &str) {
+    fn key(&self) -> (u32, &str) {
+        *self
+    }
+}
+
+
+// Keys are ordered in the same way as Data
+// (by age first, then by name)
+impl<'a> PartialEq for dyn Key + 'a {
+    fn eq(&self, other: &(dyn Key + 'a)) -> bool {
+        self.key() == other.key()
+    }
+}
+
+
+impl<'a> Eq for dyn Key + 'a {}
+
+
+impl<'a> PartialOrd for dyn Key + 'a {
+    fn partial_cmp(&self, other: &(dyn Key + 'a)) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl<'a> Ord for dyn Key + 'a {
+    fn cmp(&self, other: &(dyn Key + 'a)) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+
+// This allows the sortedcontainer to compare
+// its elements with a Key during a lookup
+impl<'a> Borrow<dyn Key + 'a> for Data {
+    fn borrow(&self) -> &(dyn Key + 'a) {
+        self
+    }
+}
+
+
 // This is required in order to use the sortedcontainer module
 // since the data needs to be displayed when using the 'p' command
 impl fmt::Display for Data {
@@ -55,83 +204,1940 @@ impl fmt::Display for Data {
 }
 
 
+// The records, ordered by age then by name (which makes
+// them their own index by age), together with a secondary
+// index giving the ages recorded for each name.
+// All the changes go through the store so that the
+// index always stays in sync with the records
+struct Store {
+    records: SortedContainer<Data>,
+    ages_by_name: SortedMap<String, SortedContainer<u32>>,
+}
 
 
+impl Store {
 
+    fn new() -> Self {
+        Store {
+            records: SortedContainer::with_balancing(Balancing::Avl),
+            ages_by_name: SortedMap::new(),
+        }
+    }
 
+    // The records, in ascending order
+    fn records(&self) -> &SortedContainer<Data> {
+        &self.records
+    }
 
-fn parse_command(input: String) -> Command {
-    let command_items: Vec<&str> = input.split_whitespace().collect();
-
-    // Fix: check if the input is empty first, so we don't panic otherwise
-    if command_items.is_empty() { 
-        return Command::Error("please insert a command".to_string());
+    // Inserts a record, updating the index
+    // @return the rejected record if it was already present
+    fn insert(&mut self, data: Data) -> Option<Data> {
+        let (age, name) = (data.age, data.name.clone());
+        let rejected = self.records.insert(data);
+        if rejected.is_none() {
+            self.ages_by_name.entry(name)
+                .or_insert_with(|| SortedContainer::with_balancing(Balancing::Avl))
+                .insert(age);
+        }
+        rejected
     }
 
-    match (command_items[0], command_items.len()) { 
-        ("p", 1) => Command::Print,
-        ("x", 1) => Command::Exit,
-        ("i", 3) => {
-            if let Ok(age) = command_items[1].parse::<u32>() {
-                Command::Insert{age: age, name: command_items[2].to_string()}
-            } else {
-                Command::Error("unable to parse int (age).".to_string())
-            }
-        },
-        ("e", 3) => {
-            if let Ok(age) = command_items[1].parse::<u32>() {
-                Command::Erase{age: age, name: command_items[2].to_string()}
-            } else {
-                Command::Error("unable to parse int (age).".to_string())
+    // Erases a record, updating the index
+    // @return the erased record, or None if it was not found
+    fn erase(&mut self, age: u32, name: &str) -> Option<Data> {
+        let key: &dyn Key = &(age, name);
+        let erased = self.records.erase(key);
+        if erased.is_some() {
+            let ages = self.ages_by_name.get_mut(name).unwrap();
+            ages.erase(&age);
+            // Names without records are dropped from the index
+            if ages.is_empty() {
+                self.ages_by_name.remove(name);
             }
+        }
+        erased
+    }
+
+    // The ages recorded for the given name, in ascending
+    // order (a lookup in the index by name)
+    fn ages_of(&self, name: &str) -> Option<&SortedContainer<u32>> {
+        self.ages_by_name.get(name)
+    }
+
+    // The records with the given age, in ascending order of
+    // name (a range over the records, starting from the
+    // smallest possible record with that age)
+    fn with_age(&self, age: u32) -> impl Iterator<Item = &Data> {
+        let first: &dyn Key = &(age, "");
+        self.records.range::<dyn Key, _>((Bound::Included(first), Bound::Unbounded))
+            .take_while(move |d| d.age == age)
+    }
+
+    // Performs an operation on the records
+    // @return true if the records did change
+    fn apply(&mut self, op: &Operation) -> bool {
+        match *op {
+            Operation::Insert{age, ref name} => self.insert(Data {age, name: name.clone()}).is_none(),
+            Operation::Erase{age, ref name} => self.erase(age, name).is_some(),
+        }
+    }
+}
+
+
+// A change made to the records
+#[derive(Debug, Clone, PartialEq)]
+enum Operation {
+    Insert{age: u32, name: String},
+    Erase{age: u32, name: String},
+}
+
+
+impl Operation {
+
+    // The operation cancelling this one
+    fn inverse(&self) -> Operation {
+        match *self {
+            Operation::Insert{age, ref name} => Operation::Erase{age, name: name.clone()},
+            Operation::Erase{age, ref name} => Operation::Insert{age, name: name.clone()},
+        }
+    }
+}
+
+
+// Operations are displayed as the command performing them
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::Insert{age, ref name} => write!(f, "i {} {}", age, name),
+            Operation::Erase{age, ref name} => write!(f, "e {} {}", age, name),
+        }
+    }
+}
+
+
+// The log of the operations which changed the records:
+// the ones done (oldest first), which can be undone by
+// applying their inverse, and the ones undone (most
+// recently undone last), which can be redone until a
+// new operation is recorded.
+// The log also keeps a savepoint for each transaction in
+// progress (the innermost last), so that all the operations
+// done since the transaction began can be rolled back
+struct History {
+    done: Vec<Operation>,
+    undone: Vec<Operation>,
+    savepoints: Vec<Savepoint>,
+}
+
+
+// The state of the log when a transaction began: the number
+// of operations done, and the operations which could be redone
+struct Savepoint {
+    done: usize,
+    undone: Vec<Operation>,
+}
+
+
+impl History {
+
+    fn new() -> Self {
+        History { done: Vec::new(), undone: Vec::new(), savepoints: Vec::new() }
+    }
+
+    // Begins a (possibly nested) transaction
+    fn begin(&mut self) {
+        self.savepoints.push(Savepoint { done: self.done.len(), undone: self.undone.clone() });
+    }
+
+    // Ends the innermost transaction keeping its operations,
+    // which become part of the enclosing transaction (if any)
+    // @return false if there is no transaction in progress
+    fn commit(&mut self) -> bool {
+        self.savepoints.pop().is_some()
+    }
+
+    // Ends the innermost transaction cancelling all of its
+    // operations, and restores the operations which could
+    // be redone when it began
+    // @return the number of operations cancelled, or None
+    //     if there is no transaction in progress
+    fn rollback(&mut self, store: &mut Store) -> Option<usize> {
+        let savepoint = self.savepoints.pop()?;
+        let cancelled = self.done.len() - savepoint.done;
+        while self.done.len() > savepoint.done {
+            let op = self.done.pop().unwrap();
+            store.apply(&op.inverse());
+        }
+        self.undone = savepoint.undone;
+        Some(cancelled)
+    }
+
+    // The number of transactions in progress
+    fn depth(&self) -> usize {
+        self.savepoints.len()
+    }
+
+    // Records an operation which has just been applied
+    // (the operations undone cannot be redone anymore)
+    fn record(&mut self, op: Operation) {
+        self.done.push(op);
+        self.undone.clear();
+    }
+
+    // Cancels the last operation done (within a transaction,
+    // only the operations of the transaction can be undone)
+    // @return the cancelled operation, if any
+    fn undo(&mut self, store: &mut Store) -> Option<&Operation> {
+        let first = self.savepoints.last().map_or(0, |savepoint| savepoint.done);
+        if self.done.len() == first {
+            return None;
+        }
+        let op = self.done.pop()?;
+        store.apply(&op.inverse());
+        self.undone.push(op);
+        self.undone.last()
+    }
+
+    // Applies again the last operation undone
+    // @return the applied operation, if any
+    fn redo(&mut self, store: &mut Store) -> Option<&Operation> {
+        let op = self.undone.pop()?;
+        store.apply(&op);
+        self.done.push(op);
+        self.done.last()
+    }
+}
+
+
+
+
+
+
+
+// Helper function: returns the last record whose
+// age is smaller than or equal to the given one
+fn floor_by_age(sc: &SortedContainer<Data>, age: u32) -> Option<&Data> {
+    match age.checked_add(1) {
+        // The smallest possible record with the next age
+        Some(next) => {
+            let key: &dyn Key = &(next, "");
+            sc.predecessor(key)
         },
-        ("c", 3) => {
-            if let Ok(age) = command_items[1].parse::<u32>() {
-                Command::Contains{age: age, name: command_items[2].to_string()}
+        None => sc.last()
+    }
+}
+
+
+// Helper function: returns the first record whose
+// age is bigger than or equal to the given one
+fn ceiling_by_age(sc: &SortedContainer<Data>, age: u32) -> Option<&Data> {
+    let key: &dyn Key = &(age, "");
+    sc.ceiling(key)
+}
+
+
+// Helper function: returns the record whose age is the
+// closest to the given one (the younger one in case of a tie)
+fn nearest_by_age(sc: &SortedContainer<Data>, age: u32) -> Option<&Data> {
+    match (floor_by_age(sc, age), ceiling_by_age(sc, age)) {
+        (Some(below), Some(above)) => {
+            if above.age - age < age - below.age {
+                Some(above)
             } else {
-                Command::Error("unable to parse int (age).".to_string())
+                Some(below)
             }
         },
+        (below, above) => below.or(above)
+    }
+}
 
-        (_, _) => Command::Error("invalid command.".to_string())
+
+// Helper function: prints the result of a lookup
+fn print_lookup(data: Option<&Data>) {
+    match data {
+        Some(data) => println!("{}", data),
+        None => println!("not found")
     }
 }
 
 
-fn main() {
+// Helper function: reads the records of a text file
+// with one record per line, written as "<age> <name>"
+// (empty lines are ignored)
+fn read_records(path: &str) -> Result<SortedContainer<Data>, String> {
+    let file = File::open(path).map_err(|e| format!("unable to open {}: {}", path, e))?;
+    let mut records = Vec::new();
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("unable to read {}: {}", path, e))?;
+        let items: Vec<&str> = line.split_whitespace().collect();
+        match items.len() {
+            0 => continue,
+            2 => match items[0].parse::<u32>() {
+                Ok(age) => records.push(Data {age, name: items[1].to_string()}),
+                Err(_) => return Err(format!("{}:{}: unable to parse int (age).", path, number + 1)),
+            },
+            _ => return Err(format!("{}:{}: expected \"<age> <name>\".", path, number + 1)),
+        }
+    }
+
+    // Sorted exports are loaded in linear time
+    let mut sc = SortedContainer::with_balancing(Balancing::Avl);
+    sc.extend(records);
+    Ok(sc)
+}
+
+
+// The on-disk format of the records (all the integers are
+// unsigned, 32 bits wide and stored in little endian order):
+//
+//     magic           4 bytes, "RSDB"
+//     version         u32, currently 1
+//     count           u32, the number of records
+//     count records:
+//         age         u32
+//         name length u32, the number of bytes of the name
+//         name        the name, encoded in UTF-8
+//
+// The records are stored in ascending order (by age, then by
+// name) without duplicates, and nothing follows the last one.
+// Any change to this layout must increase the version
+const DB_MAGIC: &[u8; 4] = b"RSDB";
+const DB_VERSION: u32 = 1;
+
+
+// Helper function: appends a length to an encoded file
+fn push_len(bytes: &mut Vec<u8>, len: usize) -> Result<(), String> {
+    let len = u32::try_from(len).map_err(|_| "too many records, or name too long.".to_string())?;
+    bytes.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+
+// Helper function: encodes the records in the on-disk format
+fn encode_records(records: &SortedContainer<Data>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(DB_MAGIC);
+    bytes.extend_from_slice(&DB_VERSION.to_le_bytes());
+    push_len(&mut bytes, records.len())?;
+    for data in records {
+        bytes.extend_from_slice(&data.age.to_le_bytes());
+        push_len(&mut bytes, data.name.len())?;
+        bytes.extend_from_slice(data.name.as_bytes());
+    }
+    Ok(bytes)
+}
+
+
+// The bytes of an encoded file not decoded yet
+struct Input<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+
+impl<'a> Input<'a> {
+
+    // Consumes the next n bytes
+    // @return an error if fewer bytes are left
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.bytes.len() {
+            return Err(format!("unexpected end of file at byte {}.", self.offset + self.bytes.len()));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        self.offset += n;
+        Ok(taken)
+    }
+
+    // Consumes the next u32
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+
+// Helper function: decodes the records of a file in the
+// on-disk format, checking every length against the bytes
+// actually available, so that corrupt files are rejected
+fn decode_records(bytes: &[u8]) -> Result<Vec<Data>, String> {
+    let mut input = Input { bytes, offset: 0 };
+    if input.take(DB_MAGIC.len()).ok() != Some(&DB_MAGIC[..]) {
+        return Err("not a rustsint database.".to_string());
+    }
+    let version = input.u32()?;
+    if version != DB_VERSION {
+        return Err(format!("unsupported version {} (expected {}).", version, DB_VERSION));
+    }
+
+    // Every record takes at least 8 bytes, so a corrupt count
+    // cannot make us allocate more than the size of the file
+    let count = input.u32()? as usize;
+    let mut records: Vec<Data> = Vec::with_capacity(count.min(input.bytes.len() / 8));
+    for number in 1..=count {
+        let age = input.u32()?;
+        let len = input.u32()? as usize;
+        let name = std::str::from_utf8(input.take(len)?)
+            .map_err(|_| format!("record {}: the name is not valid UTF-8.", number))?;
+        let data = Data {age, name: name.to_string()};
+        if records.last().is_some_and(|last| *last >= data) {
+            return Err(format!("record {}: not in ascending order.", number));
+        }
+        records.push(data);
+    }
 
-    let mut sc: SortedContainer<Data> = SortedContainer::new();
+    if !input.bytes.is_empty() {
+        return Err(format!("unexpected data at byte {}.", input.offset));
+    }
+    Ok(records)
+}
+
+
+// Helper function: saves the records to a file in the on-disk
+// format. The records are written to a temporary file which
+// then replaces the given one, so that a failure leaves the
+// previous content of the file intact
+fn save_records(path: &str, records: &SortedContainer<Data>) -> Result<(), String> {
+    let bytes = encode_records(records).map_err(|e| format!("unable to save {}: {}", path, e))?;
+    let temporary = format!("{}.tmp", path);
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&temporary)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    };
+    write().map_err(|e| format!("unable to save {}: {}", path, e))
+}
+
+
+// Helper function: loads the records of a file in the
+// on-disk format into a new store
+fn load_store(path: &str) -> Result<Store, String> {
+    let bytes = fs::read(path).map_err(|e| format!("unable to open {}: {}", path, e))?;
+    let records = decode_records(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+    let mut store = Store::new();
+    for data in records {
+        store.insert(data);
+    }
+    Ok(store)
+}
+
+
+// The write-ahead log of a database: every change made to the
+// records after the last checkpoint of the database (i.e. after
+// the records were last saved) is appended to the log before it
+// is acknowledged, so that it can be replayed after a crash.
+//
+// The log starts with a header made of the magic "RSWL" and a u32
+// version (currently 1), followed by the records. Each record is
+// made of (the integers are stored as in the database):
+//
+//     length          u32, the number of bytes of the content
+//     checksum        u32, the CRC-32 of the content
+//     content:
+//         kind        1 byte: 1 insert, 2 erase, 3 begin,
+//                     4 commit, 5 rollback, 6 checkpoint
+//         age         u32 (insert and erase only)
+//         name length u32 (insert and erase only)
+//         name        the name, encoded in UTF-8 (insert and erase only)
+//
+// A crash while appending leaves a torn record at the end of the
+// log, which is detected by its length, by its checksum or by its
+// content (the file system may also leave zero bytes after it, and
+// an empty record has a valid checksum): that record and anything
+// after it are discarded
+const LOG_MAGIC: &[u8; 4] = b"RSWL";
+const LOG_VERSION: u32 = 1;
+const LOG_HEADER_LEN: usize = 8;
+
+
+// A record of the write-ahead log
+#[derive(Debug, Clone, PartialEq)]
+enum LogRecord {
+    Operation(Operation),
+    Begin,
+    Commit,
+    Rollback,
+    Checkpoint,
+}
+
+
+// Helper function: the CRC-32 (IEEE 802.3) checksum of some bytes
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+// Helper function: encodes a record of the log
+fn encode_log_record(record: &LogRecord) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    match *record {
+        LogRecord::Operation(ref op) => {
+            let (kind, age, name) = match *op {
+                Operation::Insert{age, ref name} => (1, age, name),
+                Operation::Erase{age, ref name} => (2, age, name),
+            };
+            content.push(kind);
+            content.extend_from_slice(&age.to_le_bytes());
+            push_len(&mut content, name.len())?;
+            content.extend_from_slice(name.as_bytes());
+        },
+        LogRecord::Begin => content.push(3),
+        LogRecord::Commit => content.push(4),
+        LogRecord::Rollback => content.push(5),
+        LogRecord::Checkpoint => content.push(6),
+    }
+    let mut bytes = Vec::with_capacity(content.len() + 8);
+    push_len(&mut bytes, content.len())?;
+    bytes.extend_from_slice(&crc32(&content).to_le_bytes());
+    bytes.extend_from_slice(&content);
+    Ok(bytes)
+}
+
+
+// Helper function: decodes the content of a record of the log
+// (whose checksum is correct, so any error means that the log
+// was not written by this version of rustsint)
+// @param offset: the offset of the content in the log
+fn decode_log_content(content: &[u8], offset: usize) -> Result<LogRecord, String> {
+    let mut input = Input { bytes: content, offset };
+    let record = match input.take(1)?[0] {
+        kind @ 1 | kind @ 2 => {
+            let age = input.u32()?;
+            let len = input.u32()? as usize;
+            let name = std::str::from_utf8(input.take(len)?)
+                .map_err(|_| "the name is not valid UTF-8.".to_string())?
+                .to_string();
+            LogRecord::Operation(if kind == 1 { Operation::Insert{age, name} } else { Operation::Erase{age, name} })
+        },
+        3 => LogRecord::Begin,
+        4 => LogRecord::Commit,
+        5 => LogRecord::Rollback,
+        6 => LogRecord::Checkpoint,
+        kind => return Err(format!("unknown kind of record {}.", kind)),
+    };
+    if !input.bytes.is_empty() {
+        return Err("unexpected data at the end of a record.".to_string());
+    }
+    Ok(record)
+}
+
+
+// Helper function: decodes a log, discarding its torn final
+// record (if any): a record which is incomplete, empty, has a
+// wrong checksum or cannot be decoded is torn when it extends to
+// the end of the log or is only followed by zero bytes, and is
+// a corruption anywhere else
+// @return the records written after the last checkpoint, and
+//     the length of the log without the discarded bytes (0 if
+//     even the header is torn)
+fn decode_log(bytes: &[u8]) -> Result<(Vec<LogRecord>, usize), String> {
+    let mut header = LOG_MAGIC.to_vec();
+    header.extend_from_slice(&LOG_VERSION.to_le_bytes());
+    if bytes.len() < LOG_HEADER_LEN && header.starts_with(bytes) {
+        return Ok((Vec::new(), 0));
+    }
+    if !bytes.starts_with(LOG_MAGIC) {
+        return Err("not a rustsint log.".to_string());
+    }
+    if !bytes.starts_with(&header) {
+        return Err("unsupported version of the log.".to_string());
+    }
 
+    let mut records = Vec::new();
+    let mut valid = LOG_HEADER_LEN;
     loop {
-        let mut input = String::new();
+        let rest = &bytes[valid..];
+        if rest.len() < 8 {
+            break;
+        }
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let checksum = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]);
+        let torn = || 8 + len >= rest.len() || rest.iter().all(|&byte| byte == 0);
+        let decoded = match rest[8..].get(..len) {
+            Some([]) => Err("empty record.".to_string()),
+            Some(content) if crc32(content) == checksum => decode_log_content(content, valid + 8),
+            _ => Err("wrong checksum.".to_string()),
+        };
+        let record = match decoded {
+            Ok(record) => record,
+            Err(_) if torn() => break,
+            Err(error) => return Err(format!("record at byte {}: {}", valid, error)),
+        };
+        // Only the changes after the last checkpoint matter
+        if record == LogRecord::Checkpoint {
+            records.clear();
+        } else {
+            records.push(record);
+        }
+        valid += 8 + len;
+    }
+    Ok((records, valid))
+}
 
-        print!("> ");
-        io::stdout().flush().unwrap();
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
-                match parse_command(input) {
-                    Command::Insert{age, name} => {
-                        sc.insert(Data {age: age, name: name});
-                    },
-                    Command::Erase{age, name} => {
-                        sc.erase(Data {age: age, name: name});
-                    },
-                    Command::Contains{age, name} => {
-                        println!("{}",sc.contains(Data {age: age, name: name}));
-                    },
-                    Command::Print => {
-                        sc.print();
-                    },
-                    Command::Exit => {
-                        println!("Exiting...");
-                        break;
-                    },
-                    Command::Error(error) => {
-                        println!("Error: {}", error);
-                    }
-                }
+
+// The write-ahead log of a database, opened for appending
+struct Wal {
+    file: File,
+}
+
+
+impl Wal {
+
+    // Opens (or creates) a log, discarding its torn final record
+    // @return the log, the records written after the last
+    //     checkpoint and the number of bytes discarded
+    fn open(path: &str) -> Result<(Wal, Vec<LogRecord>, usize), String> {
+        let error = |e: io::Error| format!("unable to open {}: {}", path, e);
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path).map_err(error)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(error)?;
+        let (records, valid) = decode_log(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+
+        // New records must not follow the discarded bytes
+        if valid < bytes.len() || valid == 0 {
+            file.set_len(valid as u64).map_err(error)?;
+            if valid == 0 {
+                file.write_all(LOG_MAGIC).map_err(error)?;
+                file.write_all(&LOG_VERSION.to_le_bytes()).map_err(error)?;
             }
-            Err(error) => println!("Error: {}", error),
+            file.sync_all().map_err(error)?;
+        }
+        Ok((Wal { file }, records, bytes.len() - valid))
+    }
+
+    // Appends a record, and waits until it is on the disk
+    fn append(&mut self, record: &LogRecord) -> Result<(), String> {
+        let bytes = encode_log_record(record)?;
+        self.file.write_all(&bytes)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| format!("unable to write the log: {}", e))
+    }
+
+    // Records that the database has just been saved, and
+    // drops the records which are not needed anymore
+    fn checkpoint(&mut self) -> Result<(), String> {
+        self.append(&LogRecord::Checkpoint)?;
+        self.file.set_len(LOG_HEADER_LEN as u64)
+            .and_then(|_| self.file.sync_all())
+            .map_err(|e| format!("unable to write the log: {}", e))
+    }
+}
+
+
+// Helper function: replays the records of a log, as they were
+// performed (the transactions which did not end are rolled back)
+fn replay(store: &mut Store, records: &[LogRecord]) {
+    let mut history = History::new();
+    for record in records {
+        match *record {
+            LogRecord::Operation(ref op) => {
+                if store.apply(op) {
+                    history.record(op.clone());
+                }
+            },
+            LogRecord::Begin => history.begin(),
+            LogRecord::Commit => { history.commit(); },
+            LogRecord::Rollback => { history.rollback(store); },
+            LogRecord::Checkpoint => {},
         }
     }
+    while history.rollback(store).is_some() {}
+}
+
+
+// Helper function: the log of a database
+fn log_path(db: &str) -> String {
+    format!("{}.wal", db)
+}
+
+
+// Helper function: saves the records to a database, and
+// records the checkpoint in its log
+fn checkpoint(db: &str, records: &SortedContainer<Data>, wal: &mut Wal) -> Result<(), String> {
+    save_records(db, records)?;
+    wal.checkpoint()
+}
+
+
+// Helper function: opens a database, recovering the changes
+// made after its last checkpoint (which then becomes a new
+// checkpoint, so that the transactions rolled back during
+// the recovery are not replayed again)
+fn open_db(db: &str) -> Result<(Store, Wal), String> {
+    let mut store = if Path::new(db).exists() { load_store(db)? } else { Store::new() };
+    let (mut wal, records, discarded) = Wal::open(&log_path(db))?;
+    if discarded > 0 {
+        eprintln!("Warning: discarded a torn record ({} byte(s)) at the end of the log.", discarded);
+    }
+    if !records.is_empty() {
+        replay(&mut store, &records);
+        eprintln!("Recovered {} record(s) from the log.", records.len());
+        checkpoint(db, store.records(), &mut wal)?;
+    }
+    Ok((store, wal))
+}
+
+
+// Helper function: appends a record to the log of the database
+// (if any). A change which cannot be logged would be lost after
+// a crash, so rustsint stops before acknowledging it
+fn log(wal: &mut Option<Wal>, record: LogRecord) {
+    if let Some(ref mut wal) = *wal {
+        if let Err(error) = wal.append(&record) {
+            eprintln!("Error: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+
+// Helper function: returns the comparator listing the records
+// in the given order (ties are broken by age, then by name)
+fn comparator(order: Order) -> fn(&&Data, &&Data) -> Ordering {
+    match order {
+        Order::Age => |a, b| a.cmp(b),
+        Order::Name => |a, b| a.name.cmp(&b.name).then(a.cmp(b)),
+        Order::Length => |a, b| a.name.len().cmp(&b.name.len()).then(a.cmp(b)),
+        Order::Descending => |a, b| b.age.cmp(&a.age).then(a.name.cmp(&b.name)),
+    }
+}
+
+
+// Helper function: the operations turning the records into
+// the result of a set operation with the records of a file
+// (subset and disjoint do not change the records: none)
+fn set_changes(op: &SetOp, records: &SortedContainer<Data>, other: &SortedContainer<Data>) -> Vec<Operation> {
+    let insert = |data: &Data| Operation::Insert{age: data.age, name: data.name.clone()};
+    let erase = |data: &Data| Operation::Erase{age: data.age, name: data.name.clone()};
+    match *op {
+        SetOp::Union => other.difference(records).map(insert).collect(),
+        SetOp::Intersection => records.difference(other).map(erase).collect(),
+        SetOp::Difference => records.intersection(other).map(erase).collect(),
+        SetOp::SymmetricDifference => records.intersection(other).map(erase)
+            .chain(other.difference(records).map(insert))
+            .collect(),
+        SetOp::Subset | SetOp::Disjoint => Vec::new(),
+    }
+}
+
+
+#[allow(clippy::redundant_field_names)]
+fn parse_command(input: String) -> Command {
+    let command_items: Vec<&str> = input.split_whitespace().collect();
+
+    // Fix: check if the input is empty first, so we don't panic otherwise
+    if command_items.is_empty() { 
+        return Command::Error("please insert a command".to_string());
+    }
+
+    match (command_items[0], command_items.len()) { 
+        ("p", 1) => Command::Print,
+        ("x", 1) => Command::Exit,
+        ("i", 3) => {
+            if let Ok(age) = command_items[1].parse::<u32>() {
+                Command::Insert{age: age, name: command_items[2].to_string()}
+            } else {
+                Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("e", 3) => {
+            if let Ok(age) = command_items[1].parse::<u32>() {
+                Command::Erase{age: age, name: command_items[2].to_string()}
+            } else {
+                Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("c", 3) => {
+            if let Ok(age) = command_items[1].parse::<u32>() {
+                Command::Contains{age: age, name: command_items[2].to_string()}
+            } else {
+                Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("r", 3) => {
+            match (command_items[1].parse::<u32>(), command_items[2].parse::<u32>()) {
+                (Ok(min_age), Ok(max_age)) => Command::Range{min_age, max_age},
+                _ => Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("floor", 2) | ("ceiling", 2) | ("nearest", 2) => {
+            if let Ok(age) = command_items[1].parse::<u32>() {
+                match command_items[0] {
+                    "floor" => Command::Floor{age},
+                    "ceiling" => Command::Ceiling{age},
+                    _ => Command::Nearest{age}
+                }
+            } else {
+                Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("union", 2) | ("inter", 2) | ("diff", 2) | ("symdiff", 2) | ("subset", 2) | ("disjoint", 2) |
+        ("union!", 2) | ("inter!", 2) | ("diff!", 2) | ("symdiff!", 2) => {
+            // A trailing "!" applies the operation to the records
+            let name = command_items[0].trim_end_matches('!');
+            let apply = name.len() < command_items[0].len();
+            let op = match name {
+                "union" => SetOp::Union,
+                "inter" => SetOp::Intersection,
+                "diff" => SetOp::Difference,
+                "symdiff" => SetOp::SymmetricDifference,
+                "subset" => SetOp::Subset,
+                _ => SetOp::Disjoint
+            };
+            Command::SetOp{op, file: command_items[1].to_string(), apply}
+        },
+        ("u", 1) => Command::Undo,
+        ("r", 1) | ("redo", 1) => Command::Redo,
+        ("history", 1) => Command::History,
+        ("begin", 1) => Command::Begin,
+        ("commit", 1) => Command::Commit,
+        ("rollback", 1) => Command::Rollback,
+        ("save", 2) => Command::Save{file: command_items[1].to_string()},
+        ("load", 2) => Command::Load{file: command_items[1].to_string()},
+        ("dot", 2) => Command::Dot{file: command_items[1].to_string()},
+        ("tree", 1) => Command::Tree,
+        ("fmt", 2) => {
+            match Format::parse(command_items[1]) {
+                Some(format) => Command::Format{format},
+                None => Command::Error("unknown format (text or json).".to_string())
+            }
+        },
+        ("name", 2) => Command::ByName{name: command_items[1].to_string()},
+        ("age", 2) => {
+            if let Ok(age) = command_items[1].parse::<u32>() {
+                Command::ByAge{age}
+            } else {
+                Command::Error("unable to parse int (age).".to_string())
+            }
+        },
+        ("sort", 2) => {
+            match command_items[1] {
+                "age" => Command::Sort{order: Order::Age},
+                "name" => Command::Sort{order: Order::Name},
+                "length" => Command::Sort{order: Order::Length},
+                "desc" => Command::Sort{order: Order::Descending},
+                _ => Command::Error("unknown order (age, name, length or desc).".to_string())
+            }
+        },
+
+        (_, _) => Command::Error("invalid command.".to_string())
+    }
+}
+
+
+// The command line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    // The database loaded at startup and saved on exit
+    db: Option<String>,
+    // The file from which the commands are read (instead of stdin)
+    script: Option<String>,
+    // Whether to stop at the first error
+    strict: bool,
+    // The format of the results
+    format: Format,
+}
+
+
+// Helper function: parses the command line arguments
+// (without the name of the program)
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options { db: None, script: None, strict: false, format: Format::Text };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => match args.next() {
+                Some(file) => options.db = Some(file),
+                None => return Err("missing file after --db.".to_string()),
+            },
+            "--script" => match args.next() {
+                Some(file) => options.script = Some(file),
+                None => return Err("missing file after --script.".to_string()),
+            },
+            "--strict" => options.strict = true,
+            "--format" => match args.next().as_ref().and_then(|name| Format::parse(name)) {
+                Some(format) => options.format = format,
+                None => return Err("expected text or json after --format.".to_string()),
+            },
+            _ => return Err(format!("unknown option {}.", arg)),
+        }
+    }
+    Ok(options)
+}
+
+
+// The state of a session: the records, the history of their
+// changes, the database where they are saved (if any) and the
+// format of the results
+struct Session {
+    store: Store,
+    history: History,
+    db: Option<String>,
+    wal: Option<Wal>,
+    format: Format,
+}
+
+
+// The result of a command
+enum Reply<'a> {
+    Inserted(bool),
+    Erased(bool),
+    Bool(bool),
+    // The result of a lookup of a single record
+    Record(Option<&'a Data>),
+    // A list of records (possibly empty)
+    Records(Vec<&'a Data>),
+    // The result of a lookup of records ("not found" when empty)
+    Found(Vec<&'a Data>),
+    Undone(Option<Operation>),
+    Redone(Option<Operation>),
+    // The operations done and undone (most recently undone
+    // first), and where each transaction in progress began
+    History{done: Vec<Operation>, undone: Vec<Operation>, savepoints: Vec<usize>},
+    Transaction{depth: usize},
+    RolledBack{cancelled: usize, depth: usize},
+    Saved(usize),
+    Loaded(usize),
+    Format(Format),
+    // The number of records inserted and erased by a set
+    // operation applied to the records
+    Applied{inserted: usize, erased: usize},
+    // The number of records of a tree exported to a file
+    Exported(usize),
+    // A drawing of the tree
+    Tree(String),
+    Print(&'a SortedContainer<Data>),
+    Exit,
+}
+
+
+impl Command {
+
+    // The name of the command, as displayed in JSON
+    // (None for a command which could not be parsed)
+    fn name(&self) -> Option<&'static str> {
+        let name = match *self {
+            Command::Insert{..} => "insert",
+            Command::Erase{..} => "erase",
+            Command::Contains{..} => "contains",
+            Command::Range{..} => "range",
+            Command::Floor{..} => "floor",
+            Command::Ceiling{..} => "ceiling",
+            Command::Nearest{..} => "nearest",
+            Command::SetOp{ref op, ..} => match *op {
+                SetOp::Union => "union",
+                SetOp::Intersection => "intersection",
+                SetOp::Difference => "difference",
+                SetOp::SymmetricDifference => "symmetric_difference",
+                SetOp::Subset => "subset",
+                SetOp::Disjoint => "disjoint",
+            },
+            Command::Sort{..} => "sort",
+            Command::ByName{..} => "name",
+            Command::ByAge{..} => "age",
+            Command::Undo => "undo",
+            Command::Redo => "redo",
+            Command::History => "history",
+            Command::Begin => "begin",
+            Command::Commit => "commit",
+            Command::Rollback => "rollback",
+            Command::Save{..} => "save",
+            Command::Load{..} => "load",
+            Command::Format{..} => "fmt",
+            Command::Dot{..} => "dot",
+            Command::Tree => "tree",
+            Command::Print => "print",
+            Command::Exit => "exit",
+            Command::Error(_) => return None,
+        };
+        Some(name)
+    }
+}
+
+
+// Helper function: performs a command
+// @param loaded: where the records loaded from a file by the
+//     command are kept, so that the result can refer to them
+// @return the result of the command, or the error
+//     which prevented it from being performed
+fn execute<'a>(session: &'a mut Session, command: Command, loaded: &'a mut Option<SortedContainer<Data>>)
+    -> Result<Reply<'a>, String>
+{
+    let Session { ref mut store, ref mut history, ref db, ref mut wal, ref mut format } = *session;
+    let reply = match command {
+        Command::Insert{age, name} => {
+            let op = Operation::Insert{age, name};
+            let inserted = store.apply(&op);
+            if inserted {
+                log(wal, LogRecord::Operation(op.clone()));
+                history.record(op);
+            }
+            Reply::Inserted(inserted)
+        },
+        Command::Erase{age, name} => {
+            let op = Operation::Erase{age, name};
+            let erased = store.apply(&op);
+            if erased {
+                log(wal, LogRecord::Operation(op.clone()));
+                history.record(op);
+            }
+            Reply::Erased(erased)
+        },
+        Command::Contains{age, name} => {
+            let key: &dyn Key = &(age, name.as_str());
+            Reply::Bool(store.records().contains(key))
+        },
+        Command::Range{min_age, max_age} => {
+            // Start from the smallest possible record with
+            // the minimum age and stop after the maximum age
+            let first = Data {age: min_age, name: String::new()};
+            Reply::Records(store.records().range(first..).take_while(|d| d.age <= max_age).collect())
+        },
+        Command::Floor{age} => Reply::Record(floor_by_age(store.records(), age)),
+        Command::Ceiling{age} => Reply::Record(ceiling_by_age(store.records(), age)),
+        Command::Nearest{age} => Reply::Record(nearest_by_age(store.records(), age)),
+        Command::SetOp{op, file, apply: true} => {
+            let other = read_records(&file)?;
+            let ops = set_changes(&op, store.records(), &other);
+            let (mut inserted, mut erased) = (0, 0);
+            for op in ops {
+                if store.apply(&op) {
+                    match op {
+                        Operation::Insert{..} => inserted += 1,
+                        Operation::Erase{..} => erased += 1,
+                    }
+                    log(wal, LogRecord::Operation(op.clone()));
+                    history.record(op);
+                }
+            }
+            Reply::Applied{inserted, erased}
+        },
+        Command::SetOp{op, file, apply: false} => {
+            let other = &*loaded.insert(read_records(&file)?);
+            let result: Box<dyn Iterator<Item = &Data>> = match op {
+                SetOp::Union => Box::new(store.records().union(other)),
+                SetOp::Intersection => Box::new(store.records().intersection(other)),
+                SetOp::Difference => Box::new(store.records().difference(other)),
+                SetOp::SymmetricDifference => Box::new(store.records().symmetric_difference(other)),
+                SetOp::Subset => return Ok(Reply::Bool(store.records().is_subset(other))),
+                SetOp::Disjoint => return Ok(Reply::Bool(store.records().is_disjoint(other))),
+            };
+            Reply::Records(result.collect())
+        },
+        Command::Sort{order} => {
+            // Index the records in the requested order
+            let mut view = SortedContainer::with_comparator(
+                Balancing::Avl, Multiplicity::Set, comparator(order));
+            view.extend(store.records().iter());
+            Reply::Records(view.iter().cloned().collect())
+        },
+        Command::ByName{name} => {
+            // The records themselves, for each age in the index
+            let records = store.records();
+            let ages = store.ages_of(&name).into_iter().flatten();
+            Reply::Found(ages.filter_map(|age| records.get::<dyn Key>(&(*age, name.as_str()))).collect())
+        },
+        Command::ByAge{age} => Reply::Found(store.with_age(age).collect()),
+        Command::Undo => {
+            let op = history.undo(store).cloned();
+            if let Some(ref op) = op {
+                log(wal, LogRecord::Operation(op.inverse()));
+            }
+            Reply::Undone(op)
+        },
+        Command::Redo => {
+            let op = history.redo(store).cloned();
+            if let Some(ref op) = op {
+                log(wal, LogRecord::Operation(op.clone()));
+            }
+            Reply::Redone(op)
+        },
+        Command::History => Reply::History {
+            done: history.done.clone(),
+            undone: history.undone.iter().rev().cloned().collect(),
+            savepoints: history.savepoints.iter().map(|savepoint| savepoint.done).collect(),
+        },
+        Command::Begin => {
+            history.begin();
+            log(wal, LogRecord::Begin);
+            Reply::Transaction{depth: history.depth()}
+        },
+        Command::Commit => {
+            if !history.commit() {
+                return Err("no transaction in progress.".to_string());
+            }
+            log(wal, LogRecord::Commit);
+            Reply::Transaction{depth: history.depth()}
+        },
+        Command::Rollback => {
+            let cancelled = history.rollback(store).ok_or("no transaction in progress.")?;
+            log(wal, LogRecord::Rollback);
+            Reply::RolledBack{cancelled, depth: history.depth()}
+        },
+        Command::Save{file} => {
+            if history.depth() > 0 {
+                return Err("cannot save during a transaction.".to_string());
+            }
+            save_records(&file, store.records())?;
+            Reply::Saved(store.records().len())
+        },
+        Command::Load{file} => {
+            if history.depth() > 0 {
+                return Err("cannot load during a transaction.".to_string());
+            }
+            // The loaded records replace the current ones,
+            // and the operations before cannot be undone
+            // (the database then gets a checkpoint, since
+            // the log cannot replay a load)
+            let loaded = load_store(&file)?;
+            if let (Some(db), Some(wal)) = (db.as_ref(), wal.as_mut()) {
+                checkpoint(db, loaded.records(), wal)?;
+            }
+            *store = loaded;
+            *history = History::new();
+            Reply::Loaded(store.records().len())
+        },
+        Command::Format{format: new} => {
+            *format = new;
+            Reply::Format(new)
+        },
+        Command::Dot{file} => {
+            fs::write(&file, store.records().to_dot()).map_err(|e| format!("unable to write {}: {}", file, e))?;
+            Reply::Exported(store.records().len())
+        },
+        Command::Tree => Reply::Tree(store.records().to_ascii()),
+        Command::Print => Reply::Print(store.records()),
+        Command::Exit => Reply::Exit,
+        Command::Error(error) => return Err(error),
+    };
+    Ok(reply)
+}
+
+
+// Helper function: displays the result of a command as text
+fn display_text(result: &Result<Reply, String>) {
+    let reply = match *result {
+        Ok(ref reply) => reply,
+        Err(ref error) => return println!("Error: {}", error),
+    };
+    match *reply {
+        Reply::Inserted(inserted) => if !inserted { println!("already present") },
+        Reply::Erased(erased) => if !erased { println!("not found") },
+        Reply::Applied{inserted, erased} => println!("inserted {} and erased {} record(s)", inserted, erased),
+        Reply::Bool(value) => println!("{}", value),
+        Reply::Record(data) => print_lookup(data),
+        Reply::Records(ref records) => {
+            for data in records {
+                println!("{}", data);
+            }
+        },
+        Reply::Found(ref records) => {
+            for data in records {
+                println!("{}", data);
+            }
+            if records.is_empty() {
+                println!("not found");
+            }
+        },
+        Reply::Undone(Some(ref op)) => println!("undone: {}", op),
+        Reply::Undone(None) => println!("nothing to undo"),
+        Reply::Redone(Some(ref op)) => println!("redone: {}", op),
+        Reply::Redone(None) => println!("nothing to redo"),
+        Reply::History{ref done, ref undone, ref savepoints} => {
+            let mut savepoints = savepoints.iter().peekable();
+            for (i, op) in done.iter().enumerate() {
+                // Show where each transaction in progress began
+                while savepoints.next_if_eq(&&i).is_some() {
+                    println!("begin");
+                }
+                println!("{}: {}", i + 1, op);
+            }
+            for _ in savepoints {
+                println!("begin");
+            }
+            for op in undone {
+                println!("undone: {}", op);
+            }
+        },
+        Reply::Transaction{..} | Reply::Format(_) => {},
+        Reply::RolledBack{cancelled, depth} => {
+            println!("rolled back {} operation(s), {} transaction(s) in progress", cancelled, depth);
+        },
+        Reply::Saved(count) => println!("saved {} record(s)", count),
+        Reply::Loaded(count) => println!("loaded {} record(s)", count),
+        Reply::Exported(count) => println!("exported {} record(s)", count),
+        Reply::Tree(ref tree) => print!("{}", tree),
+        Reply::Print(records) => records.print(),
+        Reply::Exit => println!("Exiting..."),
+    }
+}
+
+
+// Helper function: a string in JSON
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+
+// Helper function: a record in JSON
+fn json_record(data: &Data) -> String {
+    format!("{{\"age\":{},\"name\":{}}}", data.age, json_string(&data.name))
+}
+
+
+// Helper function: a list in JSON
+fn json_list<T, I: IntoIterator<Item = T>, F: Fn(T) -> String>(items: I, json: F) -> String {
+    let items: Vec<String> = items.into_iter().map(json).collect();
+    format!("[{}]", items.join(","))
+}
+
+
+// Helper function: an operation in JSON
+fn json_operation(op: &Operation) -> String {
+    let (kind, age, name) = match *op {
+        Operation::Insert{age, ref name} => ("insert", age, name),
+        Operation::Erase{age, ref name} => ("erase", age, name),
+    };
+    format!("{{\"op\":\"{}\",\"age\":{},\"name\":{}}}", kind, age, json_string(name))
+}
+
+
+// Helper function: displays the result of a command as a
+// single JSON object, with the name of the command (if it
+// could be parsed) and either its error or its result
+fn display_json(command: Option<&str>, result: &Result<Reply, String>) {
+    let mut fields = Vec::new();
+    if let Some(command) = command {
+        fields.push(format!("\"command\":{}", json_string(command)));
+    }
+    let optional = |json: Option<String>| json.unwrap_or_else(|| "null".to_string());
+    match *result {
+        Err(ref error) => fields.push(format!("\"error\":{}", json_string(error))),
+        Ok(Reply::Inserted(changed)) | Ok(Reply::Erased(changed)) => fields.push(format!("\"changed\":{}", changed)),
+        Ok(Reply::Bool(value)) => fields.push(format!("\"result\":{}", value)),
+        Ok(Reply::Record(data)) => fields.push(format!("\"record\":{}", optional(data.map(json_record)))),
+        Ok(Reply::Records(ref records)) | Ok(Reply::Found(ref records)) => {
+            fields.push(format!("\"records\":{}", json_list(records.iter().cloned(), json_record)));
+        },
+        Ok(Reply::Undone(ref op)) | Ok(Reply::Redone(ref op)) => {
+            fields.push(format!("\"operation\":{}", optional(op.as_ref().map(json_operation))));
+        },
+        Ok(Reply::History{ref done, ref undone, ref savepoints}) => {
+            fields.push(format!("\"done\":{}", json_list(done, json_operation)));
+            fields.push(format!("\"undone\":{}", json_list(undone, json_operation)));
+            fields.push(format!("\"savepoints\":{}", json_list(savepoints, |i| i.to_string())));
+        },
+        Ok(Reply::Applied{inserted, erased}) => {
+            fields.push(format!("\"inserted\":{}", inserted));
+            fields.push(format!("\"erased\":{}", erased));
+        },
+        Ok(Reply::Transaction{depth}) => fields.push(format!("\"depth\":{}", depth)),
+        Ok(Reply::RolledBack{cancelled, depth}) => {
+            fields.push(format!("\"cancelled\":{}", cancelled));
+            fields.push(format!("\"depth\":{}", depth));
+        },
+        Ok(Reply::Saved(count)) | Ok(Reply::Loaded(count)) | Ok(Reply::Exported(count)) => {
+            fields.push(format!("\"count\":{}", count));
+        },
+        Ok(Reply::Tree(ref tree)) => fields.push(format!("\"tree\":{}", json_string(tree))),
+        Ok(Reply::Format(format)) => fields.push(format!("\"format\":{}", json_string(format.name()))),
+        Ok(Reply::Print(records)) => fields.push(format!("\"root\":{}", records.to_json(json_record))),
+        Ok(Reply::Exit) => {},
+    }
+    println!("{{{}}}", fields.join(","));
+}
+
+
+// Helper function: displays the result of a command in the
+// format of the session (the new one after a format command)
+fn display(format: Format, command: Option<&str>, result: &Result<Reply, String>) {
+    let format = match *result {
+        Ok(Reply::Format(new)) => new,
+        _ => format,
+    };
+    match format {
+        Format::Text => display_text(result),
+        Format::Json => display_json(command, result),
+    }
+}
+
+
+fn main() {
+
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            eprintln!("Usage: rustsint [--db <file>] [--script <file>] [--strict] [--format text|json]");
+            process::exit(2);
+        }
+    };
+
+    // The commands are read from the script, or from stdin,
+    // and the prompt is only shown to an interactive user
+    let interactive = options.script.is_none() && io::stdin().is_terminal();
+    let mut input: Box<dyn BufRead> = match options.script {
+        Some(ref script) => match File::open(script) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(error) => {
+                eprintln!("Error: unable to open {}: {}", script, error);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+
+    // A database which does not exist yet is created,
+    // while one which cannot be loaded is left untouched
+    let (store, wal) = match options.db {
+        Some(ref db) => match open_db(db) {
+            Ok((store, wal)) => (store, Some(wal)),
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                process::exit(1);
+            }
+        },
+        None => (Store::new(), None),
+    };
+    let mut session = Session { store, history: History::new(), db: options.db.clone(), wal, format: options.format };
+    let mut failed = false;
+
+    loop {
+        let mut line = String::new();
+        let mut loaded = None;
+        let format = session.format;
+
+        if interactive {
+            print!("> ");
+            io::stdout().flush().unwrap();
+        }
+        let (name, result) = match input.read_line(&mut line) {
+            // End of input
+            Ok(0) => break,
+            // Scripts may contain blank lines and comments
+            Ok(_) if !interactive && (line.trim().is_empty() || line.trim_start().starts_with('#')) => continue,
+            Ok(_) => {
+                let command = parse_command(line);
+                (command.name(), execute(&mut session, command, &mut loaded))
+            },
+            Err(error) => (None, Err(error.to_string())),
+        };
+        display(format, name, &result);
+        match result {
+            Ok(Reply::Exit) => break,
+            Ok(_) => {},
+            Err(_) => {
+                if options.strict {
+                    failed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // The transactions still in progress are not committed
+    let Session { mut store, mut history, db, wal, .. } = session;
+    if let (Some(db), Some(mut wal)) = (db, wal) {
+        while history.rollback(&mut store).is_some() {}
+        if let Err(error) = checkpoint(&db, store.records(), &mut wal) {
+            eprintln!("Error: {}", error);
+            process::exit(1);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+
+/********************** TESTS **************************/
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Store, Data, Key, History, Operation, Options, Format};
+    use super::{encode_records, decode_records, save_records, load_store, parse_args};
+    use super::{LogRecord, Wal, crc32, encode_log_record, decode_log, replay, open_db, log_path, checkpoint};
+    use super::{json_string, json_record};
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::ops::Bound;
+    use rustsint::sortedcontainer::{SortedContainer, Balancing};
+
+
+    // Helper function: the ages recorded for a name
+    fn ages(store: &Store, name: &str) -> Vec<u32> {
+        store.ages_of(name).map_or(Vec::new(), |ages| ages.iter().cloned().collect())
+    }
+
+
+    /// Test looking up records by an (age, name) key,
+    /// without building a Data
+    #[test]
+    fn test_key_lookup(){
+
+            let mut sc = SortedContainer::with_balancing(Balancing::Avl);
+            for (age, name) in [(30, "bob"), (25, "al"), (30, "al"), (41, "zed")].iter() {
+                sc.insert(Data {age: *age, name: name.to_string()});
+            }
+
+            let key: &dyn Key = &(30, "bob");
+            assert!(sc.contains(key));
+            assert_eq!(sc.get(key).map(|d| d.name.as_str()), Some("bob"));
+            let missing: &dyn Key = &(30, "zed");
+            assert!(!sc.contains(missing));
+            assert!(sc.get(missing).is_none());
+
+            // Keys are ordered as the records
+            let first: &dyn Key = &(30, "");
+            let names: Vec<&str> = sc.range::<dyn Key, _>((Bound::Included(first), Bound::Excluded(key)))
+                .map(|d| d.name.as_str()).collect();
+            assert_eq!(names, vec!["al"]);
+            assert_eq!(sc.ceiling(first).map(|d| d.key()), Some((30, "al")));
+
+            let erased = sc.erase(key).unwrap();
+            assert_eq!((erased.age, erased.name.as_str()), (30, "bob"));
+            assert!(!sc.contains(key));
+            assert_eq!(sc.len(), 3);
+    }
+
+
+    /// Test that the index by name follows the
+    /// insertions and the removals of records
+    #[test]
+    fn test_store_index(){
+
+            let mut store = Store::new();
+            for (age, name) in [(30, "bob"), (25, "bob"), (30, "al"), (41, "bob")].iter() {
+                assert!(store.insert(Data {age: *age, name: name.to_string()}).is_none());
+            }
+            assert!(store.insert(Data {age: 30, name: "bob".to_string()}).is_some());
+            assert_eq!(ages(&store, "bob"), vec![25, 30, 41]);
+            assert_eq!(ages(&store, "al"), vec![30]);
+            assert_eq!(ages(&store, "zed"), Vec::<u32>::new());
+
+            let names: Vec<&str> = store.with_age(30).map(|d| d.name.as_str()).collect();
+            assert_eq!(names, vec!["al", "bob"]);
+            assert_eq!(store.with_age(26).count(), 0);
+
+            // Records not present leave the index untouched
+            assert!(store.erase(31, "bob").is_none());
+            assert!(store.erase(30, "zed").is_none());
+            assert_eq!(ages(&store, "bob"), vec![25, 30, 41]);
+
+            assert!(store.erase(30, "bob").is_some());
+            assert_eq!(ages(&store, "bob"), vec![25, 41]);
+            assert!(store.erase(30, "al").is_some());
+            assert!(store.ages_of("al").is_none());
+            assert_eq!(store.with_age(30).count(), 0);
+            assert_eq!(store.records().len(), 2);
+    }
+
+
+    /// Test undoing and redoing operations
+    #[test]
+    fn test_undo_redo(){
+
+            let mut store = Store::new();
+            let mut history = History::new();
+            let ops = [
+                Operation::Insert{age: 30, name: "bob".to_string()},
+                Operation::Insert{age: 25, name: "al".to_string()},
+                Operation::Erase{age: 30, name: "bob".to_string()},
+            ];
+            for op in ops.iter() {
+                assert!(store.apply(op));
+                history.record(op.clone());
+            }
+            assert_eq!(store.records().len(), 1);
+
+            // Undo everything, then redo everything
+            assert_eq!(history.undo(&mut store), Some(&ops[2]));
+            assert_eq!(ages(&store, "bob"), vec![30]);
+            assert_eq!(history.undo(&mut store), Some(&ops[1]));
+            assert_eq!(history.undo(&mut store), Some(&ops[0]));
+            assert_eq!(history.undo(&mut store), None);
+            assert!(store.records().is_empty());
+            assert!(store.ages_of("bob").is_none());
+
+            assert_eq!(history.redo(&mut store), Some(&ops[0]));
+            assert_eq!(history.redo(&mut store), Some(&ops[1]));
+            assert_eq!(ages(&store, "bob"), vec![30]);
+            assert_eq!(store.records().len(), 2);
+
+            // A new operation drops the operations undone
+            let op = Operation::Insert{age: 40, name: "zed".to_string()};
+            assert!(store.apply(&op));
+            history.record(op);
+            assert_eq!(history.redo(&mut store), None);
+            assert_eq!(history.done.len(), 3);
+            assert_eq!(format!("{}", history.done[2]), "i 40 zed");
+            assert_eq!(format!("{}", history.done[2].inverse()), "e 40 zed");
+    }
+
+
+    // Helper function: applies and records an operation
+    fn run(store: &mut Store, history: &mut History, op: Operation) -> bool {
+        let applied = store.apply(&op);
+        if applied { history.record(op); }
+        applied
+    }
+
+
+    // Helper function: the records, as (age, name) pairs
+    fn records(store: &Store) -> Vec<(u32, String)> {
+        store.records().iter().map(|d| (d.age, d.name.clone())).collect()
+    }
+
+
+    /// Test committing and rolling back nested transactions
+    #[test]
+    fn test_transactions(){
+
+            let mut store = Store::new();
+            let mut history = History::new();
+            let insert = |age: u32, name: &str| Operation::Insert{age, name: name.to_string()};
+            let erase = |age: u32, name: &str| Operation::Erase{age, name: name.to_string()};
+
+            assert!(!history.commit());
+            assert!(history.rollback(&mut store).is_none());
+
+            run(&mut store, &mut history, insert(30, "bob"));
+            history.begin();
+            run(&mut store, &mut history, insert(25, "al"));
+            run(&mut store, &mut history, erase(30, "bob"));
+
+            // Nested transaction, committed into the outer one
+            history.begin();
+            run(&mut store, &mut history, insert(41, "zed"));
+            assert!(history.commit());
+            assert_eq!(history.depth(), 1);
+
+            // Nested transaction, rolled back alone
+            history.begin();
+            run(&mut store, &mut history, insert(50, "ann"));
+            run(&mut store, &mut history, erase(25, "al"));
+            assert_eq!(history.rollback(&mut store), Some(2));
+            assert_eq!(records(&store), vec![(25, "al".to_string()), (41, "zed".to_string())]);
+
+            // The outer transaction is rolled back as a whole,
+            // and the operations before it cannot be undone
+            // until it ends
+            assert!(history.undo(&mut store).is_some());
+            assert_eq!(history.rollback(&mut store), Some(2));
+            assert_eq!(records(&store), vec![(30, "bob".to_string())]);
+            assert!(store.ages_of("al").is_none());
+            assert_eq!(history.depth(), 0);
+
+            history.begin();
+            assert!(history.undo(&mut store).is_none());
+            assert!(history.commit());
+            assert!(history.undo(&mut store).is_some());
+            assert!(store.records().is_empty());
+    }
+
+
+    /// Test that a rollback restores the operations
+    /// which could be redone before the transaction
+    #[test]
+    fn test_rollback_redo(){
+
+            let mut store = Store::new();
+            let mut history = History::new();
+            run(&mut store, &mut history, Operation::Insert{age: 30, name: "bob".to_string()});
+            history.undo(&mut store);
+
+            history.begin();
+            run(&mut store, &mut history, Operation::Insert{age: 25, name: "al".to_string()});
+            assert!(history.redo(&mut store).is_none());
+            history.rollback(&mut store);
+
+            assert!(store.records().is_empty());
+            assert!(history.redo(&mut store).is_some());
+            assert_eq!(records(&store), vec![(30, "bob".to_string())]);
+    }
+
+
+    // Helper function: a store with the given records
+    fn store_of(records: &[(u32, &str)]) -> Store {
+        let mut store = Store::new();
+        for &(age, name) in records {
+            store.insert(Data {age, name: name.to_string()});
+        }
+        store
+    }
+
+
+    /// Test the on-disk format of the records
+    #[test]
+    fn test_encode_decode(){
+
+            let bytes = encode_records(store_of(&[(30, "bob"), (7, "é")]).records()).unwrap();
+            assert_eq!(bytes, vec![
+                b'R', b'S', b'D', b'B', 1, 0, 0, 0, 2, 0, 0, 0,
+                7, 0, 0, 0, 2, 0, 0, 0, 0xc3, 0xa9,
+                30, 0, 0, 0, 3, 0, 0, 0, b'b', b'o', b'b',
+            ]);
+            let decoded: Vec<(u32, String)> = decode_records(&bytes).unwrap()
+                .into_iter().map(|d| (d.age, d.name)).collect();
+            assert_eq!(decoded, vec![(7, "é".to_string()), (30, "bob".to_string())]);
+
+            let empty = encode_records(&Store::new().records).unwrap();
+            assert_eq!(empty.len(), 12);
+            assert!(decode_records(&empty).unwrap().is_empty());
+    }
+
+
+    /// Test that corrupt files are rejected
+    #[test]
+    fn test_decode_corrupt(){
+
+            let bytes = encode_records(store_of(&[(7, "al"), (30, "bob")]).records()).unwrap();
+
+            // Every truncation, and any trailing byte
+            for len in 0..bytes.len() {
+                assert!(decode_records(&bytes[..len]).is_err());
+            }
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert!(decode_records(&longer).is_err());
+
+            let corrupt = |offset: usize, byte: u8| {
+                let mut corrupt = bytes.clone();
+                corrupt[offset] = byte;
+                decode_records(&corrupt)
+            };
+            // Magic number, version and count
+            assert!(corrupt(0, b'X').is_err());
+            assert_eq!(corrupt(4, 2).unwrap_err(), "unsupported version 2 (expected 1).");
+            assert!(corrupt(8, 3).is_err());
+            assert!(corrupt(11, 0xff).is_err());
+            // Name length and content
+            assert!(corrupt(16, 1).is_err());
+            assert!(corrupt(19, 0x80).is_err());
+            assert_eq!(corrupt(20, 0xff).unwrap_err(), "record 1: the name is not valid UTF-8.");
+            // Order of the records (including duplicates)
+            assert_eq!(corrupt(12, 31).unwrap_err(), "record 2: not in ascending order.");
+            assert!(corrupt(12, 30).is_ok());
+            let mut duplicate = encode_records(store_of(&[(7, "al")]).records()).unwrap();
+            duplicate[8] = 2;
+            let record = duplicate[12..].to_vec();
+            duplicate.extend(record);
+            assert_eq!(decode_records(&duplicate).unwrap_err(), "record 2: not in ascending order.");
+    }
+
+
+    /// Test saving and loading the records
+    #[test]
+    fn test_save_load(){
+
+            let path = env::temp_dir().join(format!("rustsint-test-{}.db", process::id()));
+            let path = path.to_str().unwrap();
+
+            let store = store_of(&[(30, "bob"), (25, "bob"), (30, "al")]);
+            save_records(path, store.records()).unwrap();
+            let loaded = load_store(path).unwrap();
+            assert_eq!(records(&loaded), records(&store));
+            assert_eq!(ages(&loaded, "bob"), vec![25, 30]);
+
+            // Saving again replaces the previous content
+            save_records(path, store_of(&[(1, "zed")]).records()).unwrap();
+            assert_eq!(records(&load_store(path).unwrap()), vec![(1, "zed".to_string())]);
+
+            fs::write(path, b"RSDB").unwrap();
+            assert!(load_store(path).is_err());
+            fs::remove_file(path).unwrap();
+            assert!(load_store(path).is_err());
+    }
+
+
+    /// Test parsing the command line arguments
+    #[test]
+    fn test_parse_args(){
+
+            let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+            let default = Options { db: None, script: None, strict: false, format: Format::Text };
+            assert_eq!(parse(&[]), Ok(default));
+            assert_eq!(parse(&["--db", "x.db"]).unwrap().db, Some("x.db".to_string()));
+            let options = parse(&["--strict", "--script", "x.txt", "--format", "json"]).unwrap();
+            assert_eq!(options.script, Some("x.txt".to_string()));
+            assert!(options.strict);
+            assert_eq!(options.format, Format::Json);
+            assert!(parse(&["--db"]).is_err());
+            assert!(parse(&["--script"]).is_err());
+            assert!(parse(&["--format", "xml"]).is_err());
+            assert!(parse(&["x.db"]).is_err());
+    }
+
+
+    // Helper function: a path for a temporary database
+    fn temporary_db(name: &str) -> String {
+        let path = env::temp_dir().join(format!("rustsint-test-{}-{}.db", name, process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+
+    // Helper function: removes a temporary database and its log
+    fn remove_db(db: &str) {
+        let _ = fs::remove_file(db);
+        let _ = fs::remove_file(log_path(db));
+    }
+
+
+    // Helper function: the records of a log, encoded after its
+    // header, together with the offsets where each record ends
+    fn encode_log(records: &[LogRecord]) -> (Vec<u8>, Vec<usize>) {
+        let mut bytes = b"RSWL".to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        let mut ends = Vec::new();
+        for record in records {
+            bytes.extend(encode_log_record(record).unwrap());
+            ends.push(bytes.len());
+        }
+        (bytes, ends)
+    }
+
+
+    // Helper function: the records after replaying a log
+    fn replayed(records: &[LogRecord]) -> Vec<(u32, String)> {
+        let mut store = Store::new();
+        replay(&mut store, records);
+        self::records(&store)
+    }
+
+
+    // Helper function: a log with a committed transaction,
+    // a rolled back one, and one still in progress
+    fn sample_log() -> Vec<LogRecord> {
+        let insert = |age: u32, name: &str| LogRecord::Operation(Operation::Insert{age, name: name.to_string()});
+        let erase = |age: u32, name: &str| LogRecord::Operation(Operation::Erase{age, name: name.to_string()});
+        vec![
+            insert(30, "bob"),
+            LogRecord::Begin, insert(25, "al"), erase(30, "bob"), LogRecord::Commit,
+            LogRecord::Begin, insert(41, "zed"), LogRecord::Rollback,
+            insert(7, "é"),
+            LogRecord::Begin, insert(50, "ann"),
+        ]
+    }
+
+
+    /// Test the checksum of the log records
+    #[test]
+    fn test_crc32(){
+
+            assert_eq!(crc32(b""), 0);
+            assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+
+    /// Test replaying a log
+    #[test]
+    fn test_replay(){
+
+            let log = sample_log();
+            assert_eq!(replayed(&log), vec![(7, "é".to_string()), (25, "al".to_string())]);
+
+            let (bytes, ends) = encode_log(&log);
+            assert_eq!(decode_log(&bytes), Ok((log.clone(), bytes.len())));
+            assert_eq!(*ends.last().unwrap(), bytes.len());
+
+            // Only the records after the last checkpoint are kept
+            let mut checkpointed = log.clone();
+            checkpointed.insert(5, LogRecord::Checkpoint);
+            let (bytes, _) = encode_log(&checkpointed);
+            assert_eq!(decode_log(&bytes), Ok((log[5..].to_vec(), bytes.len())));
+
+            // Corrupt headers and records are errors, not torn records
+            assert!(decode_log(b"RSWX\x01\0\0\0").is_err());
+            assert!(decode_log(b"RSWL\x02\0\0\0").is_err());
+            let (mut bytes, ends) = encode_log(&log[..1]);
+            bytes.extend_from_slice(&[1, 0, 0, 0]);
+            bytes.extend_from_slice(&crc32(&[9]).to_le_bytes());
+            bytes.push(9);
+            assert_eq!(decode_log(&bytes), Ok((log[..1].to_vec(), ends[0])));
+            bytes.extend(encode_log_record(&log[1]).unwrap());
+            assert_eq!(decode_log(&bytes), Err(format!("record at byte {}: unknown kind of record 9.", ends[0])));
+
+            // The errors give offsets in the log
+            let (mut bytes, ends) = encode_log(&log[..1]);
+            bytes.extend_from_slice(&[1, 0, 0, 0]);
+            bytes.extend_from_slice(&crc32(&[1]).to_le_bytes());
+            bytes.push(1);
+            bytes.extend(encode_log_record(&log[1]).unwrap());
+            assert_eq!(decode_log(&bytes),
+                       Err(format!("record at byte {}: unexpected end of file at byte {}.", ends[0], ends[0] + 9)));
+    }
+
+
+    /// Test that a log truncated at any byte (as after a crash)
+    /// keeps exactly its complete records
+    #[test]
+    fn test_torn_log(){
+
+            let log = sample_log();
+            let (bytes, ends) = encode_log(&log);
+            for len in 0..=bytes.len() {
+                let complete = ends.iter().filter(|&&end| end <= len).count();
+                let valid = match complete {
+                    _ if len < 8 => 0,
+                    0 => 8,
+                    _ => ends[complete - 1],
+                };
+                assert_eq!(decode_log(&bytes[..len]), Ok((log[..complete].to_vec(), valid)));
+            }
+
+            // A corrupt final record is discarded as well
+            let mut corrupt = bytes.clone();
+            *corrupt.last_mut().unwrap() ^= 1;
+            assert_eq!(decode_log(&corrupt), Ok((log[..log.len() - 1].to_vec(), ends[ends.len() - 2])));
+
+            // So are the zero bytes left after the last record
+            for zeros in 1..=24 {
+                let mut padded = bytes.clone();
+                padded.resize(bytes.len() + zeros, 0);
+                assert_eq!(decode_log(&padded), Ok((log.clone(), bytes.len())));
+            }
+    }
+
+
+    /// Test that a corrupt record followed by other records
+    /// is reported, and the log left as it is
+    #[test]
+    fn test_corrupt_log(){
+
+            let db = temporary_db("corrupt");
+            let log = sample_log();
+            let (mut bytes, ends) = encode_log(&log);
+            bytes[ends[1] - 1] ^= 1;
+            assert_eq!(decode_log(&bytes), Err(format!("record at byte {}: wrong checksum.", ends[0])));
+
+            remove_db(&db);
+            fs::write(log_path(&db), &bytes).unwrap();
+            assert!(open_db(&db).is_err());
+            assert_eq!(fs::read(log_path(&db)).unwrap(), bytes);
+            remove_db(&db);
+    }
+
+
+    /// Test recovering a database whose log ends with zero
+    /// bytes (as the file system may leave after a crash)
+    #[test]
+    fn test_zero_padded_log(){
+
+            let db = temporary_db("zeros");
+            let log = sample_log();
+            let (mut bytes, _) = encode_log(&log[..1]);
+            let len = bytes.len();
+            bytes.resize(len + 16, 0);
+
+            remove_db(&db);
+            fs::write(log_path(&db), &bytes).unwrap();
+            let (store, wal) = open_db(&db).unwrap();
+            assert_eq!(records(&store), replayed(&log[..1]));
+            drop(wal);
+            assert_eq!(fs::read(log_path(&db)).unwrap().len(), 8);
+            remove_db(&db);
+    }
+
+
+    /// Test recovering a database from its log after a crash
+    /// happening at any byte of the log
+    #[test]
+    fn test_recovery(){
+
+            let db = temporary_db("recovery");
+            let log = sample_log();
+            let (bytes, ends) = encode_log(&log);
+            for len in 0..=bytes.len() {
+                remove_db(&db);
+                save_records(&db, store_of(&[(60, "old")]).records()).unwrap();
+                fs::write(log_path(&db), &bytes[..len]).unwrap();
+
+                let complete = ends.iter().filter(|&&end| end <= len).count();
+                let mut expected = replayed(&log[..complete]);
+                expected.push((60, "old".to_string()));
+                let (store, mut wal) = open_db(&db).unwrap();
+                assert_eq!(records(&store), expected);
+
+                // The changes logged after the recovery are not lost
+                wal.append(&LogRecord::Operation(Operation::Erase{age: 60, name: "old".to_string()})).unwrap();
+                drop(wal);
+                expected.pop();
+                let (store, _) = open_db(&db).unwrap();
+                assert_eq!(records(&store), expected);
+            }
+            remove_db(&db);
+    }
+
+
+    /// Test that a checkpoint saves the records and empties the log
+    #[test]
+    fn test_checkpoint(){
+
+            let db = temporary_db("checkpoint");
+            remove_db(&db);
+            let (mut store, mut wal) = open_db(&db).unwrap();
+            assert_eq!(fs::read(log_path(&db)).unwrap().len(), 8);
+
+            for record in sample_log() {
+                if let LogRecord::Operation(ref op) = record {
+                    store.apply(op);
+                }
+                wal.append(&record).unwrap();
+            }
+            checkpoint(&db, store.records(), &mut wal).unwrap();
+            assert_eq!(fs::read(log_path(&db)).unwrap().len(), 8);
+            drop(wal);
+            let (recovered, _) = open_db(&db).unwrap();
+            assert_eq!(records(&recovered), records(&store));
+
+            // A log ending with a checkpoint which has not been
+            // emptied yet has nothing to replay
+            let (mut bytes, _) = encode_log(&sample_log());
+            bytes.extend(encode_log_record(&LogRecord::Checkpoint).unwrap());
+            fs::write(log_path(&db), &bytes).unwrap();
+            let (recovered, _) = open_db(&db).unwrap();
+            assert_eq!(records(&recovered), records(&store));
+
+            let (wal, records, discarded) = Wal::open(&log_path(&db)).unwrap();
+            assert!(records.is_empty());
+            assert_eq!(discarded, 0);
+            drop(wal);
+            remove_db(&db);
+    }
+
+
+    /// Test the strings and the records in JSON
+    #[test]
+    fn test_json(){
+
+            assert_eq!(json_string("bob"), "\"bob\"");
+            assert_eq!(json_string("a\"b\\c\nd\u{1}é"), "\"a\\\"b\\\\c\\nd\\u0001é\"");
+            assert_eq!(json_record(&Data {age: 30, name: "bob".to_string()}), "{\"age\":30,\"name\":\"bob\"}");
+    }
+
 }
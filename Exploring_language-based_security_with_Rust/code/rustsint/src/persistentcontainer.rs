@@ -0,0 +1,481 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+// A link is an "Optional reference counted Node"
+// Nodes are never modified once created: a new version of
+// the tree only creates the nodes along the path it changes,
+// and shares all the other subtrees with the old version
+type Link<T> = Option<Rc<Node<T>>>;
+
+
+// A tree's node, containing the (shared) data, two
+// optional children, the height of the subtree rooted
+// at this node (a leaf has height 1) and its size
+struct Node<T> {
+    data:   Rc<T>,
+    left:   Link<T>,
+    right:  Link<T>,
+    height: usize,
+    size:   usize,
+}
+
+
+// Returns the height of the subtree starting at the
+// given link (an empty link has height 0)
+fn height<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.height)
+}
+
+
+// Returns the number of elements in the subtree
+// starting at the given link
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+
+// Creates a new node from its data and its children
+fn make<T>(left: Link<T>, data: Rc<T>, right: Link<T>) -> Link<T> {
+    let height = 1 + ::std::cmp::max(height(&left), height(&right));
+    let size = size(&left) + 1 + size(&right);
+    Some(Rc::new(Node { data, left, right, height, size }))
+}
+
+
+// Same as make, but restores the AVL property when the
+// heights of the children differ by two (after an insertion
+// or a removal in one of them) with one or two rotations.
+// Only the rotated nodes are created again, their subtrees
+// are shared
+fn balance<T>(left: Link<T>, data: Rc<T>, right: Link<T>) -> Link<T> {
+    let (hl, hr) = (height(&left), height(&right));
+
+    if hl > hr + 1 {
+        // Left branch too high: if the left child leans right
+        // its right child becomes the root (left-right case)
+        let l = left.unwrap();
+        if height(&l.left) >= height(&l.right) {
+            make(l.left.clone(), l.data.clone(), make(l.right.clone(), data, right))
+        } else {
+            let lr = l.right.as_ref().unwrap();
+            make(make(l.left.clone(), l.data.clone(), lr.left.clone()),
+                 lr.data.clone(),
+                 make(lr.right.clone(), data, right))
+        }
+
+    } else if hr > hl + 1 {
+        // Right branch too high (mirror case)
+        let r = right.unwrap();
+        if height(&r.right) >= height(&r.left) {
+            make(make(left, data, r.left.clone()), r.data.clone(), r.right.clone())
+        } else {
+            let rl = r.left.as_ref().unwrap();
+            make(make(left, data, rl.left.clone()),
+                 rl.data.clone(),
+                 make(rl.right.clone(), r.data.clone(), r.right.clone()))
+        }
+
+    } else {
+        make(left, data, right)
+    }
+}
+
+
+// Returns a new version of the subtree with the data inserted
+// note: the functions below are recursive, which is fine since
+// the tree is always balanced: the depth of the recursion is
+// logarithmic
+// @return None if the data is already present
+fn insert<T: Ord>(link: &Link<T>, data: T) -> Option<Link<T>> {
+    match *link {
+        None => Some(make(None, Rc::new(data), None)),
+        Some(ref n) => match n.data.as_ref().cmp(&data) {
+            Ordering::Greater => insert(&n.left, data)
+                .map(|left| balance(left, n.data.clone(), n.right.clone())),
+            Ordering::Less => insert(&n.right, data)
+                .map(|right| balance(n.left.clone(), n.data.clone(), right)),
+            Ordering::Equal => None,
+        }
+    }
+}
+
+
+// Returns a new version of the subtree without its smallest
+// element, together with that element
+// note: the link must not be empty
+fn remove_min<T>(link: &Link<T>) -> (Link<T>, Rc<T>) {
+    let n = link.as_ref().unwrap();
+    match n.left {
+        None => (n.right.clone(), n.data.clone()),
+        Some(_) => {
+            let (left, min) = remove_min(&n.left);
+            (balance(left, n.data.clone(), n.right.clone()), min)
+        }
+    }
+}
+
+
+// Returns a new version of the subtree without
+// the element equivalent to the given key
+// @return None if the element is not present
+fn remove<T, Q>(link: &Link<T>, key: &Q) -> Option<Link<T>>
+    where T: Borrow<Q>, Q: ?Sized + Ord
+{
+    let n = link.as_ref()?;
+    match n.data.as_ref().borrow().cmp(key) {
+        Ordering::Greater => remove(&n.left, key)
+            .map(|left| balance(left, n.data.clone(), n.right.clone())),
+        Ordering::Less => remove(&n.right, key)
+            .map(|right| balance(n.left.clone(), n.data.clone(), right)),
+        Ordering::Equal => {
+            if n.right.is_none() {
+                // Substitute the node with its only child (if any)
+                Some(n.left.clone())
+            } else {
+                // Substitute the node with its in-order successor
+                let (right, successor) = remove_min(&n.right);
+                Some(balance(n.left.clone(), successor, right))
+            }
+        }
+    }
+}
+
+
+/// An immutable (persistent) AVL tree: updating it gives
+/// a new container, and leaves the old one untouched.
+/// The two versions share all the subtrees which have not
+/// been changed, so an update only allocates O(log(n)) nodes
+/// and keeping old versions around (e.g. for undo or
+/// snapshots) is cheap. Cloning a container is O(1).
+/// note: the data type used must implement the Ord trait
+pub struct PersistentContainer<T> {
+    root: Link<T>,
+}
+
+
+impl<T: Ord> PersistentContainer<T> {
+
+    /// Creates a new empty container
+    pub fn new() -> Self {
+        PersistentContainer { root: None }
+    }
+
+
+    /// Returns the number of elements in the container
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+
+    /// Returns true if the container has no elements
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+
+    /// Returns the height of the tree
+    /// (0 if the container is empty)
+    pub fn height(&self) -> usize {
+        height(&self.root)
+    }
+
+
+    /// Returns an iterator visiting the elements
+    /// of the container in ascending order
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: Vec::new(), remaining: self.len() };
+        iter.push_left(&self.root);
+        iter
+    }
+
+
+    /// Returns a new container with the given element
+    /// If the element is already present the new
+    /// container is the same as this one
+    /// @param data: data to insert into the tree
+    pub fn insert(&self, data: T) -> Self {
+        match insert(&self.root, data) {
+            Some(root) => PersistentContainer { root },
+            None => self.clone(),
+        }
+    }
+
+
+    /// Returns a new container without the given element
+    /// If the element is not found the new container
+    /// is the same as this one
+    /// @param key: the element to remove, or any
+    ///     type the element can be borrowed as
+    pub fn erase<Q: ?Sized + Ord>(&self, key: &Q) -> Self
+        where T: Borrow<Q>
+    {
+        match remove(&self.root, key) {
+            Some(root) => PersistentContainer { root },
+            None => self.clone(),
+        }
+    }
+
+
+    /// Test whether the tree contains the given data
+    /// @param key: the data in object, or any type
+    ///     the data can be borrowed as
+    pub fn contains<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.get(key).is_some()
+    }
+
+
+    /// Returns a reference to the element of the
+    /// tree equivalent to the given key
+    /// @param key: the data in object, or any type
+    ///     the data can be borrowed as
+    /// @return the element if found, None otherwise
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&T>
+        where T: Borrow<Q>
+    {
+        let mut current = &self.root;
+
+        while let Some(ref n) = *current {
+            match n.data.as_ref().borrow().cmp(key) {
+                Ordering::Greater => current = &n.left,
+                Ordering::Less    => current = &n.right,
+                Ordering::Equal   => return Some(&n.data),
+            }
+        }
+
+        None
+    }
+
+
+    /// Returns true if the two containers are the same
+    /// version of the tree (i.e. they share their root)
+    pub fn ptr_eq(&self, other: &PersistentContainer<T>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+
+// Cloning only shares the root with the new container
+impl<T> Clone for PersistentContainer<T> {
+    fn clone(&self) -> Self {
+        PersistentContainer { root: self.root.clone() }
+    }
+}
+
+
+impl<T: Ord> Default for PersistentContainer<T> {
+    fn default() -> Self {
+        PersistentContainer::new()
+    }
+}
+
+
+/// A borrowing iterator over the elements of a
+/// PersistentContainer, visited in ascending order
+pub struct Iter<'a, T: 'a> {
+    stack: Vec<&'a Node<T>>,
+    remaining: usize,
+}
+
+
+impl<'a, T> Iter<'a, T> {
+
+    // Push on the stack the given node and all
+    // the nodes along its left branch
+    fn push_left(&mut self, mut link: &'a Link<T>) {
+        while let Some(ref n) = *link {
+            self.stack.push(n);
+            link = &n.left;
+        }
+    }
+}
+
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let n = self.stack.pop()?;
+        self.push_left(&n.right);
+        self.remaining -= 1;
+        Some(&n.data)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+
+impl<'a, T: Ord> IntoIterator for &'a PersistentContainer<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+
+/********************** TESTS **************************/
+
+
+#[cfg(test)]
+mod tests {
+
+    use persistentcontainer::{PersistentContainer, Link, Node};
+    use std::collections::HashSet;
+
+
+    // Helper function: checks the AVL property, the
+    // heights, the sizes and the order of the tree
+    fn check<T: Ord>(link: &Link<T>) -> usize {
+        match *link {
+            None => 0,
+            Some(ref n) => {
+                let hl = check(&n.left);
+                let hr = check(&n.right);
+                assert!(hl <= hr + 1 && hr <= hl + 1, "Node not balanced");
+                assert_eq!(n.height, 1 + hl.max(hr), "Wrong height");
+                assert_eq!(n.size, 1 + n.left.as_ref().map_or(0, |l| l.size)
+                                     + n.right.as_ref().map_or(0, |r| r.size), "Wrong size");
+                assert!(n.left.as_ref().is_none_or(|l| l.data < n.data), "Wrong order");
+                assert!(n.right.as_ref().is_none_or(|r| r.data > n.data), "Wrong order");
+                n.height
+            }
+        }
+    }
+
+
+    // Helper function: collects the addresses of
+    // all the nodes reachable from the link
+    fn nodes<T>(link: &Link<T>, found: &mut HashSet<*const Node<T>>) {
+        if let Some(ref n) = *link {
+            found.insert(&**n as *const Node<T>);
+            nodes(&n.left, found);
+            nodes(&n.right, found);
+        }
+    }
+
+
+    // Helper function: the number of nodes of the
+    // new version which do not belong to the old one
+    fn new_nodes<T>(old: &PersistentContainer<T>, new: &PersistentContainer<T>) -> usize {
+        let mut old_nodes = HashSet::new();
+        let mut new_nodes = HashSet::new();
+        nodes(&old.root, &mut old_nodes);
+        nodes(&new.root, &mut new_nodes);
+        new_nodes.difference(&old_nodes).count()
+    }
+
+
+    /// A basic test of insertion, lookup and removal
+    #[test]
+    fn test_base_api(){
+
+            let empty : PersistentContainer<u32> = PersistentContainer::new();
+            assert!(empty.is_empty());
+
+            let one = empty.insert(5);
+            let two = one.insert(3);
+            let three = two.insert(8);
+            assert_eq!(three.len(), 3);
+            assert!(three.contains(&3));
+            assert_eq!(three.get(&8), Some(&8));
+            assert!(!three.contains(&4));
+            assert_eq!(three.iter().cloned().collect::<Vec<u32>>(), vec![3, 5, 8]);
+
+            // Inserting an element already present, or erasing
+            // one which is not, gives back the same version
+            assert!(three.insert(3).ptr_eq(&three));
+            assert!(three.erase(&4).ptr_eq(&three));
+
+            let smaller = three.erase(&5);
+            assert_eq!(smaller.iter().cloned().collect::<Vec<u32>>(), vec![3, 8]);
+
+            // The old versions are untouched
+            assert!(empty.is_empty());
+            assert_eq!(one.iter().cloned().collect::<Vec<u32>>(), vec![5]);
+            assert_eq!(two.iter().cloned().collect::<Vec<u32>>(), vec![3, 5]);
+            assert_eq!(three.iter().cloned().collect::<Vec<u32>>(), vec![3, 5, 8]);
+    }
+
+
+    /// Test that every version stays valid and
+    /// keeps its content while the tree evolves
+    #[test]
+    fn test_versions(){
+
+            let mut versions = vec![PersistentContainer::new()];
+            for i in 0..200u32 {
+                let next = versions.last().unwrap().insert(i * 37 % 200);
+                versions.push(next);
+            }
+            for i in 0..100u32 {
+                let next = versions.last().unwrap().erase(&(i * 13 % 200));
+                versions.push(next);
+            }
+
+            let mut expected: Vec<u32> = Vec::new();
+            for (k, version) in versions.iter().enumerate() {
+                check(&version.root);
+                assert_eq!(version.len(), expected.len());
+                assert_eq!(version.iter().cloned().collect::<Vec<u32>>(), expected);
+                // Compute the content of the next version
+                if k < 200 {
+                    expected.push(k as u32 * 37 % 200);
+                } else if k < 300 {
+                    let erased = (k as u32 - 200) * 13 % 200;
+                    expected.retain(|x| *x != erased);
+                }
+                expected.sort();
+            }
+    }
+
+
+    /// Test that an update only creates O(log(n)) nodes,
+    /// all the other ones being shared with the old version
+    #[test]
+    fn test_structural_sharing(){
+
+            let mut sc = PersistentContainer::new();
+            for i in 0..1000u32 {
+                let next = sc.insert(i);
+                assert!(new_nodes(&sc, &next) <= 2 * next.height() + 2);
+                sc = next;
+            }
+            check(&sc.root);
+
+            for i in (0..1000u32).filter(|i| i % 3 == 0) {
+                let next = sc.erase(&i);
+                assert!(new_nodes(&sc, &next) <= 2 * sc.height() + 2);
+                sc = next;
+            }
+            check(&sc.root);
+
+            // An update of a big tree allocates a few nodes only
+            for i in 1000..100_000u32 { sc = sc.insert(i); }
+            assert!(sc.height() <= 24);
+            let updated = sc.insert(200_000);
+            assert_eq!(updated.len(), sc.len() + 1);
+            assert!(new_nodes(&sc, &updated) <= 2 * sc.height() + 2);
+
+            // Nodes are freed as soon as no version uses them
+            let before = sc.clone();
+            let root = before.root.clone().unwrap();
+            drop(sc);
+            drop(updated);
+            drop(before);
+            assert_eq!(::std::rc::Rc::strong_count(&root), 1);
+    }
+
+}
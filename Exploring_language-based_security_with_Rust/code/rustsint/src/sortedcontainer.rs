@@ -1,4 +1,9 @@
-use std::fmt::Display;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::iter::{FromIterator, Peekable};
+use std::ops::{Bound, RangeBounds};
 
 // A link is defined as an "Optional boxed Node"
 // Nodes are boxed so that they are allocated on the heap
@@ -7,223 +12,2190 @@ use std::fmt::Display;
 type Link<T> = Option<Box<Node<T>>>;
 
 
-// A tree's node, containing a generic data type
-// and two optional children
+// A tree's node, containing a generic data type,
+// two optional children, the height of the subtree
+// rooted at this node (a leaf has height 1) and its
+// size (the number of elements it contains).
+// In a multiset, the elements equivalent to the data
+// which have been inserted afterwards are kept (in
+// insertion order) in the duplicates of the node.
+// The duplicates are kept rather than counted because
+// equivalent elements are not necessarily equal (with a
+// comparator looking at a part of the elements only, e.g.
+// a key), and because iterating over the occurrences or
+// taking them back (erase_all, into_iter) must not require
+// T: Clone. This costs an empty Vec (24 bytes on 64-bit
+// targets) in every node, sets included, plus a heap
+// allocation for each element present more than once.
 #[derive(Debug)]
 struct Node<T> {
-    data:  T,
-    left:  Link<T>,
-    right: Link<T>,
+    data:   T,
+    dups:   Vec<T>,
+    left:   Link<T>,
+    right:  Link<T>,
+    height: usize,
+    size:   usize,
 }
 
 
+impl<T> Node<T> {
+
+    // Creates a new leaf containing the given data
+    fn new(data: T) -> Self {
+        Node { data, dups: Vec::new(), left: None, right: None, height: 1, size: 1 }
+    }
+
+    // Number of occurrences of the data stored in this node
+    fn count(&self) -> usize {
+        1 + self.dups.len()
+    }
+
+    // Returns the i-th occurrence of the data
+    // (0 being the data itself)
+    fn occurrence(&self, i: usize) -> &T {
+        if i == 0 { &self.data } else { &self.dups[i - 1] }
+    }
+
+    // Recomputes the height and the size of this
+    // node from the ones of its children
+    fn update(&mut self) {
+        self.height = 1 + ::std::cmp::max(height(&self.left), height(&self.right));
+        self.size = size(&self.left) + self.count() + size(&self.right);
+    }
+
+    // The balance factor of the node: a positive value
+    // means that the left branch is higher than the right one
+    fn balance_factor(&self) -> isize {
+        height(&self.left) as isize - height(&self.right) as isize
+    }
+}
+
+
+// Returns the height of the subtree starting at the
+// given link (an empty link has height 0)
+fn height<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.height)
+}
+
+
+// Returns the number of elements in the subtree
+// starting at the given link
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+
+// Returns the node holding the element of the given rank
+// (in ascending order) in the subtree starting at the given link
+fn node_at_mut<T>(link: &mut Link<T>, mut rank: usize) -> Option<&mut Node<T>> {
+    let mut current = link.as_mut();
+
+    while let Some(n) = current {
+        let left = size(&n.left);
+        if rank < left {
+            current = n.left.as_mut();
+        } else if rank < left + n.count() {
+            return Some(n);
+        } else {
+            rank -= left + n.count();
+            current = n.right.as_mut();
+        }
+    }
+
+    None
+}
+
+
+// Rotates the subtree starting at the given link to the left:
+//
+//       a                b
+//      / \              / \
+//     x   b     =>     a   z
+//        / \          / \
+//       y   z        x   y
+//
+// note: the link must contain a node with a right child
+fn rotate_left<T>(link: &mut Link<T>) {
+    let mut a = link.take().unwrap();
+    let mut b = a.right.take().unwrap();
+    a.right = b.left.take();
+    a.update();
+    b.left = Some(a);
+    b.update();
+    *link = Some(b);
+}
+
+
+// Rotates the subtree starting at the given link to the right
+// (mirror image of rotate_left)
+// note: the link must contain a node with a left child
+fn rotate_right<T>(link: &mut Link<T>) {
+    let mut b = link.take().unwrap();
+    let mut a = b.left.take().unwrap();
+    b.left = a.right.take();
+    b.update();
+    a.right = Some(b);
+    a.update();
+    *link = Some(a);
+}
+
+
+// Updates the height and the size of the node contained in
+// the given link and, if the tree is balanced, restores the AVL property
+// (children heights differ by at most one) with one or two
+// rotations. The subtrees of the node must already be valid
+// AVL trees.
+fn fix_node<T>(link: &mut Link<T>, balancing: Balancing) {
+
+    let bf = match *link {
+        None => return,
+        Some(ref mut n) => { n.update(); n.balance_factor() }
+    };
+
+    if balancing == Balancing::None { return; }
+
+    if bf > 1 {
+        // Left branch too high: if the left child leans right
+        // we first need to rotate it (left-right case)
+        let n = link.as_mut().unwrap();
+        if n.left.as_ref().unwrap().balance_factor() < 0 {
+            rotate_left(&mut n.left);
+        }
+        rotate_right(link);
+
+    } else if bf < -1 {
+        // Right branch too high (mirror case)
+        let n = link.as_mut().unwrap();
+        if n.right.as_ref().unwrap().balance_factor() > 0 {
+            rotate_right(&mut n.right);
+        }
+        rotate_left(link);
+    }
+}
+
+
+// Builds a perfectly balanced tree out of the first n groups
+// of equivalent elements (data and duplicates) provided by the
+// iterator, which must be sorted: the left half is built first,
+// then its root is taken, then the right half is built.
+// note: the recursion depth is logarithmic in n
+fn build_balanced<T, I>(n: usize, groups: &mut I) -> Link<T>
+    where I: Iterator<Item = (T, Vec<T>)>
+{
+    if n == 0 { return None; }
+
+    let left = build_balanced(n / 2, groups);
+    let (data, dups) = groups.next().unwrap();
+    let right = build_balanced(n - n / 2 - 1, groups);
+
+    let mut node = Box::new(Node::new(data));
+    node.dups = dups;
+    node.left = left;
+    node.right = right;
+    node.update();
+    Some(node)
+}
+
+
+// Joins two trees with a node whose data lies between them:
+// all the elements of left must be smaller than the node,
+// all the elements of right bigger.
+// With an AVL tree the node is attached along the right branch
+// of the left tree (or the left branch of the right tree) where
+// the height matches the one of the other tree, and the nodes
+// above are fixed: this takes O(|height(left) - height(right)|)
+// note: the node must have no children
+fn join<T>(left: Link<T>, mut mid: Box<Node<T>>, right: Link<T>, balancing: Balancing) -> Link<T> {
+    let (hl, hr) = (height(&left), height(&right));
+    let mut path = Path::new();
+
+    if balancing == Balancing::Avl && hl > hr + 1 {
+        let mut current = left;
+        while height(&current) > hr + 1 {
+            let mut n = current.unwrap();
+            current = n.right.take();
+            path.push(n, Side::Right);
+        }
+        mid.left = current;
+        mid.right = right;
+    } else if balancing == Balancing::Avl && hr > hl + 1 {
+        let mut current = right;
+        while height(&current) > hl + 1 {
+            let mut n = current.unwrap();
+            current = n.left.take();
+            path.push(n, Side::Left);
+        }
+        mid.left = left;
+        mid.right = current;
+    } else {
+        mid.left = left;
+        mid.right = right;
+    }
+
+    let mut link = Some(mid);
+    fix_node(&mut link, balancing);
+    path.rebuild(link, Some(balancing))
+}
+
+
+// Joins two trees: all the elements of left must be smaller
+// than the ones of right. The smallest node of right is
+// detached and used to join the two trees
+fn join_trees<T>(left: Link<T>, right: Link<T>, balancing: Balancing) -> Link<T> {
+    let mut path = Path::new();
+    let mut current = match right {
+        None => return left,
+        Some(n) => n,
+    };
+
+    while let Some(next) = current.left.take() {
+        path.push(current, Side::Left);
+        current = next;
+    }
+    let rest = current.right.take();
+    let right = path.rebuild(rest, Some(balancing));
+
+    join(left, current, right, balancing)
+}
+
+
+// Splits a tree in two: the nodes whose data goes to the
+// right according to the given function (which must be
+// monotonic with respect to the order of the tree) form
+// the second tree, the other ones the first tree.
+// While walking down, each node is detached with the subtree
+// lying on the same side as itself; on the way back the
+// pieces are joined one by one. With an AVL tree the costs
+// of the joins telescope, so the split takes O(log(n))
+fn split<T, F>(mut link: Link<T>, mut goes_right: F, balancing: Balancing) -> (Link<T>, Link<T>)
+    where F: FnMut(&T) -> bool
+{
+    let mut pieces = Vec::new();
+    while let Some(mut n) = link {
+        if goes_right(&n.data) {
+            link = n.left.take();
+            pieces.push((n, Side::Left));
+        } else {
+            link = n.right.take();
+            pieces.push((n, Side::Right));
+        }
+    }
+
+    let (mut left, mut right) = (None, None);
+    while let Some((mut n, side)) = pieces.pop() {
+        match side {
+            Side::Left => {
+                let subtree = n.right.take();
+                right = join(right, n, subtree, balancing);
+            }
+            Side::Right => {
+                let subtree = n.left.take();
+                left = join(subtree, n, left, balancing);
+            }
+        }
+    }
+
+    (left, right)
+}
+
+
+// The side of a node from which a child has been detached
+#[derive(Clone, Copy)]
+enum Side { Left, Right }
+
+
+// The path from the root of a tree down to a given link.
+// While walking down, each node is detached from its
+// parent and moved onto the path together with the side
+// of the child we are heading to: this way, once the
+// tree has been modified, the nodes can be put back
+// (and fixed) from the bottom to the top without any
+// recursion and without keeping multiple mutable
+// references to the same tree.
+struct Path<T> {
+    nodes: Vec<(Box<Node<T>>, Side)>,
+}
+
+
+impl<T> Path<T> {
+
+    fn new() -> Self {
+        Path { nodes: Vec::new() }
+    }
+
+    // Adds a node to the bottom of the path
+    // note: the child on the given side must have
+    // been detached from the node
+    fn push(&mut self, node: Box<Node<T>>, side: Side) {
+        self.nodes.push((node, side));
+    }
+
+    // Consumes the path, attaching the given link at the
+    // bottom and each node back to its parent
+    // @param link: the new content of the bottom of the path
+    // @param balancing: if some, every node is fixed after
+    //     its child has been attached (use Balancing::None
+    //     to update the sizes without moving the nodes)
+    // @return the root of the rebuilt tree
+    fn rebuild(mut self, mut link: Link<T>, balancing: Option<Balancing>) -> Link<T> {
+        while let Some((mut n, side)) = self.nodes.pop() {
+            match side {
+                Side::Left  => n.left  = link,
+                Side::Right => n.right = link,
+            }
+            link = Some(n);
+            if let Some(b) = balancing {
+                fix_node(&mut link, b);
+            }
+        }
+        link
+    }
+}
+
+
+/// The order of the elements of a SortedContainer
+/// A comparator compares an element (L) either with another
+/// element or with a key (R) used to look elements up.
+/// note: it must define a total order, consistent over time:
+/// with a partial one (e.g. f64 and NaN) the values which
+/// cannot be ordered would be taken as equivalent, silently
+/// corrupting the tree
+pub trait Compare<L, R: ?Sized = L> {
+    /// Compares an element with another element or a key
+    fn compare(&self, l: &L, r: &R) -> Ordering;
+}
+
+
+/// The natural order of the elements, given by their Ord
+/// implementation: elements can be looked up by any type
+/// they can be borrowed as (see Borrow)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Natural;
+
+
+impl<L: Borrow<R>, R: ?Sized + Ord> Compare<L, R> for Natural {
+    fn compare(&self, l: &L, r: &R) -> Ordering {
+        l.borrow().cmp(r)
+    }
+}
+
+
+/// Any function comparing two elements can be used as a
+/// comparator (closures, function pointers, boxed trait
+/// objects such as Box<dyn Fn(&T, &T) -> Ordering>, ...):
+/// elements can then only be looked up by elements
+impl<T, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    fn compare(&self, l: &T, r: &T) -> Ordering {
+        self(l, r)
+    }
+}
+
+
+// Whether two comparators of type C are known to order the
+// elements in the same way: a comparator without any state
+// (Natural, a closure capturing nothing) is defined by its type,
+// while two function pointers or boxed closures of the same
+// type may be completely different orders
+fn same_order<C>() -> bool {
+    ::std::mem::size_of::<C>() == 0
+}
+
+
+/// The strategy used by a SortedContainer to keep its shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balancing {
+    /// Plain binary search tree: nodes are never moved,
+    /// so the shape depends on the insertion order
+    /// (inserting sorted data produces a linked list)
+    None,
+    /// AVL tree: the tree is rebalanced after every insertion
+    /// and removal so that its height stays logarithmic
+    Avl,
+}
+
+
+/// Whether a SortedContainer accepts the same element twice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+    /// Each element can be present only once
+    Set,
+    /// Elements can be present several times: the occurrences
+    /// of an element are kept together in the same node
+    Multiset,
+}
+
+
+/// The reason why a SortedContainer is not valid (see
+/// SortedContainer::validate). The position of a node is the
+/// index, in ascending order, of its first element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantError {
+    /// The data of the node is smaller than the previous one
+    Unordered { position: usize },
+    /// The data of the node is equivalent to the previous
+    /// one (or, in a set, the node has duplicates)
+    Duplicate { position: usize },
+    /// A duplicate of the node is not equivalent to its data
+    MisplacedOccurrence { position: usize },
+    /// The height stored in the node is not the one of its subtree
+    WrongHeight { position: usize, stored: usize, actual: usize },
+    /// The size stored in the node is not the one of its subtree
+    WrongSize { position: usize, stored: usize, actual: usize },
+    /// The heights of the children of the node
+    /// differ by more than one in an AVL tree
+    Unbalanced { position: usize, balance_factor: isize },
+}
+
+
+impl Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvariantError::Unordered{position} =>
+                write!(f, "element {} is smaller than the previous one", position),
+            InvariantError::Duplicate{position} =>
+                write!(f, "element {} is equivalent to the previous one", position),
+            InvariantError::MisplacedOccurrence{position} =>
+                write!(f, "node at {} has an occurrence of a different element", position),
+            InvariantError::WrongHeight{position, stored, actual} =>
+                write!(f, "node at {} has height {} instead of {}", position, stored, actual),
+            InvariantError::WrongSize{position, stored, actual} =>
+                write!(f, "node at {} has size {} instead of {}", position, stored, actual),
+            InvariantError::Unbalanced{position, balance_factor} =>
+                write!(f, "node at {} has balance factor {}", position, balance_factor),
+        }
+    }
+}
+
+
+impl Error for InvariantError {}
+
+
 /// A binary search tree for a generic data types
-/// The tree act as a set, therefore it is not possible
-/// to add the same element twice
-/// note: the data type used must implement the
-/// Display and PartialOrd traits
-pub struct SortedContainer<T> {
+/// By default the tree act as a set, therefore it is not
+/// possible to add the same element twice (unless it is
+/// created as a multiset)
+/// The elements are ordered by a comparator (see Compare):
+/// by default their natural order, which requires the data
+/// type used to implement the Ord trait, i.e. to be totally
+/// ordered (floating point numbers for instance have to be
+/// wrapped in a type defining how NaN is ordered)
+/// note: the data type must implement Display in order to
+/// print the tree
+pub struct SortedContainer<T, C = Natural> {
     root: Link<T>,
+    balancing: Balancing,
+    multiplicity: Multiplicity,
+    comparator: C,
+    // Whether the invariants are checked after
+    // every mutation (see set_validation)
+    validating: bool,
 }
 
 
 
-impl<T: Display + PartialOrd> SortedContainer<T> {
+impl<T: Ord> SortedContainer<T> {
 
-    /// Creates a new sortedcontainer
+    /// Creates a new sortedcontainer which keeps itself
+    /// balanced (an AVL tree, see with_balancing to opt out)
     pub fn new() -> Self {
-        SortedContainer { root: None }
+        SortedContainer::with_balancing(Balancing::Avl)
     }
 
 
-    /// Prints the content of the tree
-    /// indented according to the depth
-    pub fn print(&self) {
+    /// Creates a new sortedcontainer using the
+    /// given balancing strategy
+    /// @param balancing: how the tree should keep its shape
+    pub fn with_balancing(balancing: Balancing) -> Self {
+        SortedContainer::with_policy(balancing, Multiplicity::Set)
+    }
 
-        // Helper function: print tree recursively
-        fn _printtree<T: Display>(current: &Link<T>, level: usize){
-            match current {
-                &None => println!("{:width$}(nil)", "", width = level),
-                &Some(ref n) => {
-                    println!("{:width$}{}", "", n.data, width = level);
-                    _printtree(&n.left,level+1);
-                    _printtree(&n.right,level+1);
+
+    /// Creates a new sortedcontainer using the given
+    /// balancing strategy and multiplicity
+    /// @param balancing: how the tree should keep its shape
+    /// @param multiplicity: whether duplicates are accepted
+    pub fn with_policy(balancing: Balancing, multiplicity: Multiplicity) -> Self {
+        SortedContainer::with_comparator(balancing, multiplicity, Natural)
+    }
+
+
+    /// Creates a new sortedcontainer (an AVL tree, see new)
+    /// holding the given elements, arranged in a perfectly
+    /// balanced tree
+    /// note: the tree is built in linear time when the elements
+    /// are already sorted; otherwise they are sorted first (in
+    /// O(n log(n))) and only the first of the equivalent
+    /// elements is kept
+    /// @param iter: the elements, preferably in ascending order
+    pub fn from_sorted_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut sc = SortedContainer::new();
+        sc.assign(iter.into_iter().collect());
+        sc
+    }
+}
+
+
+impl<T, C: Compare<T>> SortedContainer<T, C> {
+
+    /// Creates a new sortedcontainer ordering its elements
+    /// with the given comparator, e.g. a closure
+    /// `|a: &T, b: &T| b.cmp(a)` for a descending order
+    /// @param balancing: how the tree should keep its shape
+    /// @param multiplicity: whether duplicates are accepted
+    /// @param comparator: the order of the elements
+    pub fn with_comparator(balancing: Balancing, multiplicity: Multiplicity, comparator: C) -> Self {
+        SortedContainer { root: None, balancing, multiplicity, comparator, validating: false }
+    }
+
+
+    /// Returns the comparator ordering the elements
+    pub fn comparator(&self) -> &C {
+        &self.comparator
+    }
+
+
+    // Empties the container, returning its elements in order
+    fn take_all(&mut self) -> IntoIter<T> {
+        IntoIter { elements: into_elements(self.root.take()).into_iter() }
+    }
+
+
+    // Replaces the whole tree with a perfectly balanced one
+    // holding the given elements (see from_sorted_iter)
+    // note: the stable sort keeps the equivalent elements in
+    // their original order, which is the insertion order of
+    // the occurrences in a multiset
+    fn assign(&mut self, mut elements: Vec<T>) {
+        let sorted = elements.windows(2).all(|w| self.comparator.compare(&w[0], &w[1]) != Ordering::Greater);
+        if !sorted {
+            elements.sort_by(|a, b| self.comparator.compare(a, b));
+        }
+
+        // Gather the equivalent elements
+        let mut groups: Vec<(T, Vec<T>)> = Vec::with_capacity(elements.len());
+        for data in elements {
+            let equivalent = match groups.last() {
+                Some((last, _)) => self.comparator.compare(last, &data) == Ordering::Equal,
+                None => false,
+            };
+            if !equivalent {
+                groups.push((data, Vec::new()));
+            } else if self.multiplicity == Multiplicity::Multiset {
+                groups.last_mut().unwrap().1.push(data);
+            }
+        }
+
+        let n = groups.len();
+        self.root = build_balanced(n, &mut groups.into_iter());
+        self.debug_check();
+    }
+
+
+    /// Returns the balancing strategy of the container
+    pub fn balancing(&self) -> Balancing {
+        self.balancing
+    }
+
+
+    /// Returns whether the container is a set or a multiset
+    pub fn multiplicity(&self) -> Multiplicity {
+        self.multiplicity
+    }
+
+
+    /// Enables or disables the check of all the invariants of
+    /// the tree (see check_invariants) after every mutation
+    /// note: this only has an effect in debug builds, and
+    /// makes each mutation take O(n)
+    /// @param enabled: whether the checks should be performed
+    pub fn set_validation(&mut self, enabled: bool) {
+        self.validating = enabled;
+    }
+
+
+    /// Checks that the tree is a valid binary search tree:
+    /// the elements are in ascending order without duplicates
+    /// (the occurrences of a multiset being kept in their node),
+    /// the heights and the sizes stored in the nodes are right
+    /// and, with an AVL tree, the nodes are balanced
+    /// note: the nodes are visited in order, each one being
+    /// compared with the previous one and checked against
+    /// the values stored in its children
+    /// @return the first violation found, if any
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let mut stack: Vec<&Node<T>> = Vec::new();
+        let mut link = &self.root;
+        let mut previous: Option<&T> = None;
+        let mut position = 0;
+
+        loop {
+            while let Some(ref n) = *link {
+                stack.push(n);
+                link = &n.left;
+            }
+            let n = match stack.pop() {
+                None => return Ok(()),
+                Some(n) => n,
+            };
+
+            if let Some(previous) = previous {
+                match self.comparator.compare(previous, &n.data) {
+                    Ordering::Less    => {}
+                    Ordering::Equal   => return Err(InvariantError::Duplicate{position}),
+                    Ordering::Greater => return Err(InvariantError::Unordered{position}),
                 }
             }
+            if self.multiplicity == Multiplicity::Set && !n.dups.is_empty() {
+                return Err(InvariantError::Duplicate{position: position + 1});
+            }
+            if n.dups.iter().any(|dup| self.comparator.compare(dup, &n.data) != Ordering::Equal) {
+                return Err(InvariantError::MisplacedOccurrence{position});
+            }
+
+            let actual = 1 + ::std::cmp::max(height(&n.left), height(&n.right));
+            if n.height != actual {
+                return Err(InvariantError::WrongHeight{position, stored: n.height, actual});
+            }
+            let actual = size(&n.left) + n.count() + size(&n.right);
+            if n.size != actual {
+                return Err(InvariantError::WrongSize{position, stored: n.size, actual});
+            }
+            let balance_factor = n.balance_factor();
+            if self.balancing == Balancing::Avl && balance_factor.abs() > 1 {
+                return Err(InvariantError::Unbalanced{position, balance_factor});
+            }
+
+            previous = Some(&n.data);
+            position += n.count();
+            link = &n.right;
         }
+    }
+
 
-        // Use an helper function
-        _printtree(&self.root,0);
+    /// Same as validate, but panics with a
+    /// description of the violation found
+    pub fn check_invariants(&self) {
+        if let Err(error) = self.validate() {
+            panic!("invalid SortedContainer: {}", error);
+        }
     }
 
 
-    /// Insert a new element into the tree
-    /// If the element is already present this
-    /// method does nothing
-    /// @param data: data to insert into the 
-    pub fn insert(&mut self, data: T) {
+    // Runs check_invariants after a mutation, if
+    // enabled (see set_validation) in a debug build
+    fn debug_check(&self) {
+        if cfg!(debug_assertions) && self.validating {
+            self.check_invariants();
+        }
+    }
+
+
+    /// Returns the height of the tree
+    /// (0 if the container is empty)
+    pub fn height(&self) -> usize {
+        height(&self.root)
+    }
+
+
+    /// Returns the number of elements in the container
+    /// (each occurrence counts in a multiset)
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+
+    /// Returns true if the container has no elements
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+
+    /// Returns an iterator visiting the elements
+    /// of the container in ascending order
+    /// (the occurrences of an element in a multiset
+    /// are visited in insertion order)
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter::new(&self.root)
+    }
+
+
+    /// Returns an iterator visiting, in ascending order,
+    /// only the elements which fall within the given range
+    /// note: the subtrees lying outside of the range are
+    /// never visited
+    /// @param range: the bounds of the elements to return
+    ///     (e.g. `3..7`, `3..=7`, `..7`, ...), expressed either
+    ///     with the data type or a type it can be borrowed as
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T>
+        where Q: ?Sized, R: RangeBounds<Q>, C: Compare<T, Q>
+    {
+
+        let mut front = Vec::new();
+        let mut back  = Vec::new();
+
+        // Collect the path to the smallest element within the
+        // lower bound: we only keep the nodes we could return
+        let mut link = &self.root;
+        while let Some(ref n) = *link {
+            let inside = match range.start_bound() {
+                Bound::Included(b) => self.comparator.compare(&n.data, b) != Ordering::Less,
+                Bound::Excluded(b) => self.comparator.compare(&n.data, b) == Ordering::Greater,
+                Bound::Unbounded   => true,
+            };
+            if inside { front.push(&**n); link = &n.left; } else { link = &n.right; }
+        }
+
+        // Same thing for the biggest element within the upper bound
+        let mut link = &self.root;
+        while let Some(ref n) = *link {
+            let inside = match range.end_bound() {
+                Bound::Included(b) => self.comparator.compare(&n.data, b) != Ordering::Greater,
+                Bound::Excluded(b) => self.comparator.compare(&n.data, b) == Ordering::Less,
+                Bound::Unbounded   => true,
+            };
+            if inside { back.push(&**n); link = &n.right; } else { link = &n.left; }
+        }
+
+        // The range is empty if the bounds left no element
+        // or if the smallest element comes after the biggest
+        let empty = match (front.last(), back.last()) {
+            (Some(lo), Some(hi)) => self.comparator.compare(&lo.data, &hi.data) == Ordering::Greater,
+            _ => true,
+        };
+
+        if empty {
+            front.clear();
+            back.clear();
+        }
+
+        Range { front, back, front_done: 0, back_done: 0 }
+    }
 
-        // Find the link that should contain
-        // the given data
-        let l = self.find_pos(&data);
 
-        // Add the data only if the link
-        // is empty
-        if l.is_none() {
-           *l = Some(Box::new(
-                Node {data: data, left: None, right: None}
-           ));
+    /// Insert a new element into the tree
+    /// If the element is already present the tree
+    /// is left untouched and the data is given back
+    /// (in a multiset, the data is instead added as
+    /// a new occurrence of the element)
+    /// @param data: data to insert into the tree
+    /// @return None if the data has been inserted, or
+    ///     the rejected data if it was already present
+    pub fn insert(&mut self, data: T) -> Option<T> {
+
+        // Walk down the tree detaching the nodes along
+        // the way until we reach the position of the data
+        let mut path = Path::new();
+        let mut current = self.root.take();
+
+        let rejected = loop {
+            match current {
+                None => {
+                    current = Some(Box::new(Node::new(data)));
+                    break None;
+                }
+                Some(mut n) => {
+                    let order = self.comparator.compare(&n.data, &data);
+                    if      order == Ordering::Greater { current = n.left.take();  path.push(n, Side::Left);  }
+                    else if order == Ordering::Less    { current = n.right.take(); path.push(n, Side::Right); }
+                    else if self.multiplicity == Multiplicity::Multiset {
+                        // New occurrence: the shape does not change
+                        // but the sizes along the path do
+                        n.dups.push(data);
+                        current = Some(n);
+                        fix_node(&mut current, Balancing::None);
+                        self.root = path.rebuild(current, Some(Balancing::None));
+                        self.debug_check();
+                        return None;
+                    } else {
+                        // Already present
+                        current = Some(n);
+                        break Some(data);
+                    }
+                }
+            }
+        };
+
+        // Put the nodes back, fixing them only
+        // if the tree did change
+        if rejected.is_none() {
+            self.root = path.rebuild(current, Some(self.balancing));
+        } else {
+            self.root = path.rebuild(current, None);
         }
+
+        self.debug_check();
+        rejected
     }
 
 
     /// Test whether the tree contains 
     /// the given data
-    /// @param data: the data in object
+    /// @param key: the data in object, or any type
+    ///     the data can be borrowed as
     /// @return true if the tree contains the
     ///     data, false otherwise
-    pub fn contains(&mut self, data: T) -> bool {
-        match *self.find_pos(&data) {
-            None => false,
-            _    => true
-        }
+    pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool
+        where C: Compare<T, Q>
+    {
+        self.find_by(|data| self.comparator.compare(data, key)).is_some()
     }
 
 
-    /// Remove an element from the tree. If
-    /// the element is not found nothing is done
-    /// @param data: the element to remove
-    pub fn erase(&mut self, data: T) {
-
-        // Helper function: find the leftmost link starting
-        // from the provided link (note: this function is
-        // used so that we can target the in-order successor
-        // of the element we want to erase if the latter has
-        // two children)
-        // @param n: the link from where to start
-        // @return a mutable reference to the leftmost link
-        fn _take_leftmost<'a, T>(n: &'a mut Link<T>) -> &'a mut Link<T>{
-            
-            let has_left_branch = n.as_ref().unwrap().left.is_some();
+    /// Returns a reference to the element of the
+    /// tree equivalent to the given key
+    /// @param key: the data in object, or any type
+    ///     the data can be borrowed as
+    /// @return the element if found, None otherwise
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+        where C: Compare<T, Q>
+    {
+        self.find_by(|data| self.comparator.compare(data, key))
+    }
+
+
+    /// Returns the number of occurrences of an element
+    /// (always 0 or 1 unless the container is a multiset)
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn count<Q: ?Sized>(&self, key: &Q) -> usize
+        where C: Compare<T, Q>
+    {
+        self.find_node_by(|data| self.comparator.compare(data, key)).map_or(0, |n| n.count())
+    }
 
-            if has_left_branch { 
-                _take_leftmost(&mut n.as_mut().unwrap().left)
-            } else { n }
+
+    /// Returns the k-th smallest element of the container
+    /// (k starts from 0, each occurrence counts in a multiset)
+    /// @param k: the position of the element in sorted order
+    /// @return the element, or None if k is out of bounds
+    pub fn select(&self, mut k: usize) -> Option<&T> {
+        let mut current = &self.root;
+
+        // Use the sizes of the subtrees to know
+        // on which side the element lies
+        while let Some(ref n) = *current {
+            let left = size(&n.left);
+            if k < left {
+                current = &n.left;
+            } else if k < left + n.count() {
+                return Some(n.occurrence(k - left));
+            } else {
+                k -= left + n.count();
+                current = &n.right;
+            }
         }
 
-        // Find the target node to erase
-        let target = self.find_pos(&data);
-        if target.is_none() { return; } // None not found
+        None
+    }
 
-        // Remove the target and get its content
-        // note1: we use the method `take` so that we can
-        // "move" the content of the target node by 
-        // temporarily replacing it with a None
-        // note2: we need to dereference the boxed content
-        // in order to tear it apart and let the borrow 
-        // checker distinguish the different fields 
-        let mut target_content = *target.take().unwrap();       
-
-        if target_content.left.is_some() && target_content.right.is_some() {
-            // Node has two children ?
 
-            // Substitute the data of the target with 
-            // the content of the in-order successor
-            target_content.data = {
-                // Find the leftmost node on the right branch
-                let leftmost = _take_leftmost(&mut target_content.right);
+    /// Returns the number of elements strictly smaller than
+    /// the given key (i.e. the position the key has, or would
+    /// have, in sorted order)
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn rank<Q: ?Sized>(&self, key: &Q) -> usize
+        where C: Compare<T, Q>
+    {
+        let mut rank = 0;
+        let mut current = &self.root;
+
+        while let Some(ref n) = *current {
+            match self.comparator.compare(&n.data, key) {
+                Ordering::Greater => current = &n.left,
+                Ordering::Less    => {
+                    // The node and its whole left branch are smaller
+                    rank += size(&n.left) + n.count();
+                    current = &n.right;
+                }
+                Ordering::Equal   => return rank + size(&n.left),
+            }
+        }
+
+        rank
+    }
+
+
+    /// Returns the smallest element of the container
+    pub fn first(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+
+    /// Returns the biggest element of the container
+    pub fn last(&self) -> Option<&T> {
+        self.iter().next_back()
+    }
+
+
+    /// Returns the biggest element smaller than
+    /// or equivalent to the given key
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn floor<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+        where C: Compare<T, Q>
+    {
+        self.range::<Q, _>((Bound::Unbounded, Bound::Included(key))).next_back()
+    }
+
+
+    /// Returns the smallest element bigger than
+    /// or equivalent to the given key
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn ceiling<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+        where C: Compare<T, Q>
+    {
+        self.range::<Q, _>((Bound::Included(key), Bound::Unbounded)).next()
+    }
+
+
+    /// Returns the biggest element strictly smaller
+    /// than the given key
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn predecessor<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+        where C: Compare<T, Q>
+    {
+        self.range::<Q, _>((Bound::Unbounded, Bound::Excluded(key))).next_back()
+    }
+
+
+    /// Returns the smallest element strictly bigger
+    /// than the given key
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    pub fn successor<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+        where C: Compare<T, Q>
+    {
+        self.range::<Q, _>((Bound::Excluded(key), Bound::Unbounded)).next()
+    }
+
+
+    /// Removes the smallest element of the container
+    /// (only one of its occurrences in a multiset)
+    /// @return the removed element, or None if the
+    ///     container is empty
+    pub fn pop_first(&mut self) -> Option<T> {
+        // Go left as long as possible
+        let leftmost = |_: &C, n: &Node<T>| {
+            if n.left.is_some() { Ordering::Greater } else { Ordering::Equal }
+        };
+        self.remove_by(leftmost, false).map(|(data, _)| data)
+    }
+
+
+    /// Removes the biggest element of the container
+    /// (only one of its occurrences in a multiset)
+    /// @return the removed element, or None if the
+    ///     container is empty
+    pub fn pop_last(&mut self) -> Option<T> {
+        // Go right as long as possible
+        let rightmost = |_: &C, n: &Node<T>| {
+            if n.right.is_some() { Ordering::Less } else { Ordering::Equal }
+        };
+        self.remove_by(rightmost, false).map(|(data, _)| data)
+    }
+
+
+    /// Returns the median element of the container (the
+    /// lower one if the number of elements is even)
+    /// @return the median, or None if the container is empty
+    pub fn median(&self) -> Option<&T> {
+        if self.is_empty() { return None; }
+        self.select((self.len() - 1) / 2)
+    }
+
+
+    /// Returns an iterator visiting, in ascending order, the
+    /// elements which are in self or in other (or in both)
+    /// note: the two containers are merged in linear time
+    /// (as for all the set operations, the elements are ordered
+    /// by the comparator of self: when the comparators may
+    /// differ, e.g. function pointers, the elements of other are
+    /// sorted again first, in O(m log(m)));
+    /// with multisets an element occurs as many times as in
+    /// the container where it is the most frequent
+    /// @param other: the container to merge with self
+    pub fn union<'a>(&'a self, other: &'a SortedContainer<T, C>) -> Union<'a, T, C> {
+        Union { merge: Merge::new(self, other) }
+    }
+
+
+    /// Returns an iterator visiting, in ascending order,
+    /// the elements which are both in self and in other
+    /// note: with multisets an element occurs as many times as
+    /// in the container where it is the least frequent
+    /// @param other: the container to intersect with self
+    pub fn intersection<'a>(&'a self, other: &'a SortedContainer<T, C>) -> Intersection<'a, T, C> {
+        Intersection { merge: Merge::new(self, other) }
+    }
+
+
+    /// Returns an iterator visiting, in ascending order,
+    /// the elements which are in self but not in other
+    /// note: with multisets each occurrence in other
+    /// cancels one occurrence in self
+    /// @param other: the container to subtract from self
+    pub fn difference<'a>(&'a self, other: &'a SortedContainer<T, C>) -> Difference<'a, T, C> {
+        Difference { merge: Merge::new(self, other) }
+    }
+
+
+    /// Returns an iterator visiting, in ascending order, the
+    /// elements which are either in self or in other, but not
+    /// in both
+    /// note: with multisets each occurrence in one container
+    /// cancels one occurrence in the other
+    /// @param other: the container to compare with self
+    pub fn symmetric_difference<'a>(&'a self, other: &'a SortedContainer<T, C>)
+        -> SymmetricDifference<'a, T, C>
+    {
+        SymmetricDifference { merge: Merge::new(self, other) }
+    }
+
+
+    /// Returns true if all the elements of self
+    /// are also in other
+    /// @param other: the container to compare with self
+    pub fn is_subset(&self, other: &SortedContainer<T, C>) -> bool {
+        self.len() <= other.len() && self.difference(other).next().is_none()
+    }
+
+
+    /// Returns true if self and other have
+    /// no element in common
+    /// @param other: the container to compare with self
+    pub fn is_disjoint(&self, other: &SortedContainer<T, C>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+
+    /// Splits the container in two at the given key: the
+    /// elements bigger than or equivalent to the key are moved
+    /// to a new container (with the same balancing and
+    /// multiplicity), the smaller ones stay in self
+    /// note: this takes O(log(n)) with an AVL tree, and
+    /// O(height) otherwise
+    /// @param key: the element, or any type
+    ///     the element can be borrowed as
+    /// @return the container of the moved elements
+    pub fn split_off<Q: ?Sized>(&mut self, key: &Q) -> SortedContainer<T, C>
+        where C: Compare<T, Q> + Clone
+    {
+        let comparator = &self.comparator;
+        let goes_right = |data: &T| comparator.compare(data, key) != Ordering::Less;
+        let (left, right) = split(self.root.take(), goes_right, self.balancing);
+        self.root = left;
+        self.debug_check();
+
+        let other = SortedContainer {
+            root: right,
+            balancing: self.balancing,
+            multiplicity: self.multiplicity,
+            comparator: self.comparator.clone(),
+            validating: self.validating,
+        };
+        other.debug_check();
+        other
+    }
+
+
+    /// Moves all the elements of other into self, leaving
+    /// other empty (in a set, the elements of other which are
+    /// already in self are dropped)
+    /// note: when all the elements of one container are smaller
+    /// than the ones of the other, the two trees are simply
+    /// joined (in O(log(n)) with an AVL tree); otherwise, or when
+    /// the comparators may differ (see same_order), they are
+    /// merged as with extend
+    /// @param other: the container to empty into self
+    pub fn append(&mut self, other: &mut SortedContainer<T, C>) {
+        if other.is_empty() { return; }
+
+        // The tree of other must already have the shape, the
+        // content and the order which self expects
+        let compatible = same_order::<C>() &&
+            (self.balancing == Balancing::None || other.balancing == self.balancing) &&
+            (self.multiplicity == Multiplicity::Multiset || other.multiplicity == self.multiplicity);
+
+        if compatible {
+            let (first, last) = (other.first().unwrap(), other.last().unwrap());
+            if self.last().is_none_or(|max| self.comparator.compare(max, first) == Ordering::Less) {
+                self.root = join_trees(self.root.take(), other.root.take(), self.balancing);
+                self.debug_check();
+                return;
+            }
+            if self.first().is_some_and(|min| self.comparator.compare(last, min) == Ordering::Less) {
+                self.root = join_trees(other.root.take(), self.root.take(), self.balancing);
+                self.debug_check();
+                return;
+            }
+        }
+
+        let elements = other.take_all();
+        self.extend(elements);
+    }
+
+
+    /// Remove an element from the tree. If
+    /// the element is not found nothing is done
+    /// note: in a multiset only one occurrence is
+    /// removed (same as erase_one)
+    /// @param key: the element to remove, or any
+    ///     type the element can be borrowed as
+    /// @return the removed element, or None if
+    ///     it was not found
+    pub fn erase<Q: ?Sized>(&mut self, key: &Q) -> Option<T>
+        where C: Compare<T, Q>
+    {
+        self.erase_one(key)
+    }
+
+
+    /// Remove one occurrence of an element (the last
+    /// one inserted). The node of the element is only
+    /// removed from the tree with its last occurrence.
+    /// @param key: the element to remove, or any
+    ///     type the element can be borrowed as
+    /// @return the removed occurrence, or None if
+    ///     the element was not found
+    pub fn erase_one<Q: ?Sized>(&mut self, key: &Q) -> Option<T>
+        where C: Compare<T, Q>
+    {
+        self.remove_by(|c, n| c.compare(&n.data, key), false).map(|(data, _)| data)
+    }
+
+
+    /// Remove all the occurrences of an element
+    /// @param key: the element to remove, or any
+    ///     type the element can be borrowed as
+    /// @return the removed occurrences in insertion
+    ///     order (empty if the element was not found)
+    pub fn erase_all<Q: ?Sized>(&mut self, key: &Q) -> Vec<T>
+        where C: Compare<T, Q>
+    {
+        match self.remove_by(|c, n| c.compare(&n.data, key), true) {
+            None => Vec::new(),
+            Some((data, mut dups)) => {
+                dups.insert(0, data);
+                dups
+            }
+        }
+    }
+
+
+    // Remove the element for which the given function returns
+    // Ordering::Equal (together with all its occurrences).
+    // The function tells how an element is ordered with respect
+    // to the one we are looking for, so that the search can
+    // be done with any kind of key.
+    // @param cmp: the comparison function
+    // @return the removed element, if found
+    pub(crate) fn erase_by<F>(&mut self, mut cmp: F) -> Option<T>
+        where F: FnMut(&T) -> Ordering
+    {
+        self.remove_by(|_, n| cmp(&n.data), true).map(|(data, _)| data)
+    }
+
+
+    // Remove from the tree the element for which the given
+    // function returns Ordering::Equal (see erase_by)
+    // @param cmp: the comparison function, it receives the
+    //     comparator of the container (which cannot be captured
+    //     while the container is borrowed) and the nodes, so
+    //     that it can also look at their shape
+    // @param all: if false and the element has several
+    //     occurrences, only the last one is removed,
+    //     otherwise the whole node is unlinked
+    // @return the removed data and duplicates
+    fn remove_by<F>(&mut self, mut cmp: F, all: bool) -> Option<(T, Vec<T>)>
+        where F: FnMut(&C, &Node<T>) -> Ordering
+    {
+        // Walk down the tree detaching the nodes
+        // along the way until we find the target
+        let mut path = Path::new();
+        let mut current = self.root.take();
+
+        let target = loop {
+            match current {
+                None => {
+                    // Not found: put the nodes back as they were
+                    self.root = path.rebuild(None, None);
+                    return None;
+                }
+                Some(mut n) => {
+                    match cmp(&self.comparator, &n) {
+                        Ordering::Greater => { current = n.left.take();  path.push(n, Side::Left);  }
+                        Ordering::Less    => { current = n.right.take(); path.push(n, Side::Right); }
+                        Ordering::Equal   => break n,
+                    }
+                }
+            }
+        };
+
+        Some(self.remove_at(path, target, all))
+    }
+
+
+    // Removes the node found at the bottom of a path (or only
+    // its last occurrence, see remove_by), and puts the nodes
+    // of the path back
+    // @param path: the path from the root down to the node
+    // @param target: the node, detached from the path
+    // @param all: whether all the occurrences are removed
+    // @return the removed data and duplicates
+    fn remove_at(&mut self, path: Path<T>, mut target: Box<Node<T>>, all: bool) -> (T, Vec<T>) {
+        if !all {
+            if let Some(data) = target.dups.pop() {
+                // Remove the last occurrence only: the shape
+                // does not change but the sizes along the path do
+                let mut current = Some(target);
+                fix_node(&mut current, Balancing::None);
+                self.root = path.rebuild(current, Some(Balancing::None));
+                self.debug_check();
+                return (data, Vec::new());
+            }
+        }
+
+        // Remove the target and get its content
+        let (removed, mut replacement) = if target.left.is_some() && target.right.is_some() {
+            // Node has two children ?
+
+            // Find the leftmost node on the right branch
+            // (the in-order successor of the target)
+            let mut successor_path = Path::new();
+            let mut leftmost = target.right.take().unwrap();
+            while let Some(left) = leftmost.left.take() {
+                successor_path.push(leftmost, Side::Left);
+                leftmost = left;
+            }
+
+            // Unlink the node from the tree
+            let rest = leftmost.right.take();
+            target.right = successor_path.rebuild(rest, Some(self.balancing));
+
+            // Substitute the data of the target with 
+            // the content of the in-order successor
+            let removed = (
+                ::std::mem::replace(&mut target.data, leftmost.data),
+                ::std::mem::replace(&mut target.dups, leftmost.dups),
+            );
+
+            // Put the target (with different data) 
+            // node back on the tree
+            (removed, Some(target))
+
+        } else {
+            // Substitute the target with its only child (if any)
+            // note: we need to dereference the boxed content
+            // in order to tear it apart and let the borrow
+            // checker distinguish the different fields
+            let target = *target;
+            ((target.data, target.dups), target.left.or(target.right))
+        };
+
+        fix_node(&mut replacement, self.balancing);
+        self.root = path.rebuild(replacement, Some(self.balancing));
+
+        self.debug_check();
+        removed
+    }
+
+
+
+
+    // This function is used internally to find the element
+    // for which the given function returns Ordering::Equal
+    // (see erase_by)
+    // @param cmp: the comparison function
+    // @return a reference to the element, or None if the tree
+    //     does not contain such element
+    pub(crate) fn find_by<F>(&self, cmp: F) -> Option<&T>
+        where F: FnMut(&T) -> Ordering
+    {
+        self.find_node_by(cmp).map(|n| &n.data)
+    }
+
+
+    // Same as find_by, but returns a mutable reference.
+    // note: the caller must not change the data in any way
+    // that would change its ordering
+    pub(crate) fn get_mut_by<F>(&mut self, cmp: F) -> Option<&mut T>
+        where F: FnMut(&T) -> Ordering
+    {
+        self.get_node_mut_by(cmp).map(|n| &mut n.data)
+    }
+
+
+    // Finds the node for which the given
+    // function returns Ordering::Equal
+    fn find_node_by<F>(&self, mut cmp: F) -> Option<&Node<T>>
+        where F: FnMut(&T) -> Ordering
+    {
+        let mut current = &self.root;
+
+        while let Some(ref n) = *current {
+            match cmp(&n.data) {
+                Ordering::Greater => current = &n.left,
+                Ordering::Less    => current = &n.right,
+                Ordering::Equal   => return Some(n),
+            }
+        }
+
+        None
+    }
+
+
+    // Walks down the tree to the element for which the given
+    // function returns Ordering::Equal, or to the empty link
+    // where such an element would be inserted (see Cursor)
+    // @param cmp: the comparison function
+    // @return a cursor at that position
+    pub(crate) fn cursor_by<F>(&mut self, mut cmp: F) -> Cursor<'_, T, C>
+        where F: FnMut(&T) -> Ordering
+    {
+        let mut path = Path::new();
+        let mut current = self.root.take();
+        let mut rank = 0;
+
+        while let Some(mut n) = current {
+            match cmp(&n.data) {
+                Ordering::Greater => { current = n.left.take(); path.push(n, Side::Left); }
+                Ordering::Less    => {
+                    rank += size(&n.left) + n.count();
+                    current = n.right.take();
+                    path.push(n, Side::Right);
+                }
+                Ordering::Equal   => {
+                    rank += size(&n.left);
+                    current = Some(n);
+                    break;
+                }
+            }
+        }
+
+        Cursor { container: Some(self), path, current, rank }
+    }
+
+
+    // Same as find_node_by, but returns a mutable reference
+    fn get_node_mut_by<F>(&mut self, mut cmp: F) -> Option<&mut Node<T>>
+        where F: FnMut(&T) -> Ordering
+    {
+        let mut current = self.root.as_mut();
+
+        while let Some(n) = current {
+            match cmp(&n.data) {
+                Ordering::Greater => current = n.left.as_mut(),
+                Ordering::Less    => current = n.right.as_mut(),
+                Ordering::Equal   => return Some(n),
+            }
+        }
+
+        None
+    }
+
+
+    /*
+    // This function exists for nothing but illustrative purposes:
+    // it shows a different approach for inserting a node without
+    // the use of the find_pos function
+    fn _insertnode(&self, current: &mut Link<T>, new_node: Node<T>){
+        match current {
+            &mut None => {
+                // Insert Node
+                *current = Some(Box::new(new_node));
+            }
+
+            &mut Some(ref mut n) => {
+                if n.data > new_node.data {
+                    self._insertnode(&mut n.left, new_node);
+                } else if n.data < new_node.data {
+                    self._insertnode(&mut n.right, new_node);
+                } else {
+                    // Node is already present
+                    return;
+                }
+            }
+        }
+    }
+    */
+
+}
+
+
+// A position in a tree, reached by SortedContainer::cursor_by.
+// The nodes above the position are detached on a path (as when
+// inserting or removing an element), so that the element found
+// there can be accessed or removed, or a new element inserted
+// there, without walking down the tree again. The tree is put
+// back together when the cursor is consumed or dropped: a cursor
+// which is leaked (e.g. with mem::forget) leaves the container
+// empty, and all its elements are leaked with it
+pub(crate) struct Cursor<'a, T: 'a, C: 'a> {
+    container: Option<&'a mut SortedContainer<T, C>>,
+    path: Path<T>,
+    // The node found, or None if no element was found
+    current: Link<T>,
+    // The number of elements before the position
+    rank: usize,
+}
+
+
+impl<'a, T, C> Cursor<'a, T, C> {
+
+    // The element found, if any
+    pub(crate) fn get(&self) -> Option<&T> {
+        self.current.as_ref().map(|n| &n.data)
+    }
+
+    // Same as get, but returns a mutable reference
+    pub(crate) fn get_mut(&mut self) -> Option<&mut T> {
+        self.current.as_mut().map(|n| &mut n.data)
+    }
+
+    // Puts the tree back together (only once)
+    // @param balancing: see Path::rebuild
+    // @return the container, if the tree was not put back yet
+    fn close(&mut self, balancing: Option<Balancing>) -> Option<&'a mut SortedContainer<T, C>> {
+        let container = self.container.take()?;
+        let path = ::std::mem::replace(&mut self.path, Path::new());
+        container.root = path.rebuild(self.current.take(), balancing);
+        Some(container)
+    }
+}
+
+
+impl<'a, T, C: Compare<T>> Cursor<'a, T, C> {
+
+    // Converts the cursor into a mutable reference to the
+    // element found, bound to the lifetime of the container
+    // note: the shape of the tree does not change, the element
+    // is reached again by its rank (without any comparison)
+    pub(crate) fn into_mut(mut self) -> Option<&'a mut T> {
+        self.current.as_ref()?;
+        let rank = self.rank;
+        let container = self.close(None)?;
+        node_at_mut(&mut container.root, rank).map(|n| &mut n.data)
+    }
+
+    // Inserts an element at the position of the cursor
+    // note: no element must have been found, and the new
+    // element must be ordered as the one looked for
+    // @return a mutable reference to the inserted element,
+    //     reached again by its rank once the tree is balanced
+    pub(crate) fn insert(mut self, data: T) -> &'a mut T {
+        assert!(self.current.is_none(), "an element was found at the position of the cursor");
+        self.current = Some(Box::new(Node::new(data)));
+        let rank = self.rank;
+        let balancing = self.container.as_ref().map(|c| c.balancing);
+        let container = self.close(balancing).unwrap();
+        container.debug_check();
+        node_at_mut(&mut container.root, rank).map(|n| &mut n.data).unwrap()
+    }
+
+    // Removes the element found, together with all its occurrences
+    // @return the removed element, if any
+    pub(crate) fn remove(mut self) -> Option<T> {
+        let target = self.current.take()?;
+        let container = self.container.take()?;
+        let path = ::std::mem::replace(&mut self.path, Path::new());
+        Some(container.remove_at(path, target, true).0)
+    }
+}
+
+
+impl<'a, T, C> Drop for Cursor<'a, T, C> {
+    fn drop(&mut self) {
+        self.close(None);
+    }
+}
+
+
+// Helper function: the data of a node, followed by
+// its number of occurrences if there are duplicates
+fn label<T: Display>(n: &Node<T>) -> String {
+    if n.dups.is_empty() {
+        n.data.to_string()
+    } else {
+        format!("{} (x{})", n.data, n.count())
+    }
+}
+
+
+impl<T: Display, C> SortedContainer<T, C> {
+
+    /// Prints the content of the tree
+    /// indented according to the depth
+    pub fn print(&self) {
+
+        // Visit the tree in pre-order using an explicit
+        // stack of (link, level) so that the depth of the
+        // tree does not matter
+        let mut stack = vec![(&self.root, 0)];
+
+        while let Some((current, level)) = stack.pop() {
+            match *current {
+                None => println!("{:width$}(nil)", "", width = level),
+                Some(ref n) => {
+                    println!("{:width$}{}", "", label(n), width = level);
+                    // The right branch is pushed first
+                    // so that the left one is printed first
+                    stack.push((&n.right, level+1));
+                    stack.push((&n.left, level+1));
+                }
+            }
+        }
+    }
+
+    /// Exports the tree in the Graphviz DOT language: each node
+    /// is labelled with its data, its height and its balance
+    /// factor, and the missing children of the inner nodes are
+    /// drawn as points (so that a lone child keeps its side)
+    /// @return the source of the graph (e.g. for "dot -Tpng")
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph SortedContainer {\n    ordering=out;\n    node [shape=box];\n");
+
+        // Visit the tree in pre-order using an explicit
+        // stack of (link, id of its node)
+        let mut stack = vec![(&self.root, 0)];
+        let mut next_id = 1;
+
+        while let Some((current, id)) = stack.pop() {
+            match *current {
+                None => dot.push_str(&format!("    n{} [shape=point];\n", id)),
+                Some(ref n) => {
+                    let text = label(n).replace('\\', "\\\\").replace('"', "\\\"");
+                    dot.push_str(&format!("    n{} [label=\"{}\\nh={} bf={}\"];\n",
+                                          id, text, n.height, n.balance_factor()));
+                    if n.left.is_some() || n.right.is_some() {
+                        let (left, right) = (next_id, next_id + 1);
+                        next_id += 2;
+                        dot.push_str(&format!("    n{} -> n{};\n    n{} -> n{};\n", id, left, id, right));
+                        stack.push((&n.right, right));
+                        stack.push((&n.left, left));
+                    }
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Draws the tree with box-drawing characters: one line per
+    /// node, with its data, its height and its balance factor,
+    /// below its parent (the left child first). The missing
+    /// children of the inner nodes are drawn as (nil)
+    /// @return the drawing, ending with a new line
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::new();
+
+        // Visit the tree in pre-order using an explicit stack of
+        // (link, prefix of its line, connector to its parent,
+        // continuation of the connector on its children's lines)
+        let mut stack = vec![(&self.root, String::new(), "", "")];
+
+        while let Some((current, prefix, connector, continuation)) = stack.pop() {
+            match *current {
+                None => ascii.push_str(&format!("{}{}(nil)\n", prefix, connector)),
+                Some(ref n) => {
+                    ascii.push_str(&format!("{}{}{} [h={} bf={}]\n",
+                                            prefix, connector, label(n), n.height, n.balance_factor()));
+                    if n.left.is_some() || n.right.is_some() {
+                        let prefix = prefix + continuation;
+                        stack.push((&n.right, prefix.clone(), "\u{2514}\u{2500}\u{2500} ", "    "));
+                        stack.push((&n.left, prefix, "\u{251c}\u{2500}\u{2500} ", "\u{2502}   "));
+                    }
+                }
+            }
+        }
+
+        ascii
+    }
+}
+
+
+impl<T, C> SortedContainer<T, C> {
+
+    /// Exports the tree in JSON: each node is an object with
+    /// its data, the other occurrences of its data (only when
+    /// there are duplicates) and its children, e.g.
+    /// {"data":2,"left":{"data":1,"left":null,"right":null},"right":null}
+    /// @param element: converts an element to JSON
+    /// @return the tree, or null if it is empty
+    pub fn to_json<F: Fn(&T) -> String>(&self, element: F) -> String {
+        let mut json = String::new();
+
+        // Visit the tree in pre-order using an explicit stack
+        // of the links still to export and of the text to
+        // write after them
+        enum Step<'a, T: 'a> {
+            Link(&'a Link<T>),
+            Text(&'static str),
+        }
+        let mut stack = vec![Step::Link(&self.root)];
+
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Text(text) => json.push_str(text),
+                Step::Link(current) => match *current {
+                    None => json.push_str("null"),
+                    Some(ref n) => {
+                        json.push_str(&format!("{{\"data\":{}", element(&n.data)));
+                        if !n.dups.is_empty() {
+                            let dups: Vec<String> = n.dups.iter().map(&element).collect();
+                            json.push_str(&format!(",\"dups\":[{}]", dups.join(",")));
+                        }
+                        json.push_str(",\"left\":");
+                        stack.push(Step::Text("}"));
+                        stack.push(Step::Link(&n.right));
+                        stack.push(Step::Text(",\"right\":"));
+                        stack.push(Step::Link(&n.left));
+                    }
+                },
+            }
+        }
+
+        json
+    }
+}
+
+
+impl<T, C: Compare<T> + Default> Default for SortedContainer<T, C> {
+    fn default() -> Self {
+        SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set, C::default())
+    }
+}
+
+
+// Same as from_sorted_iter, with any comparator
+impl<T, C: Compare<T> + Default> FromIterator<T> for SortedContainer<T, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut sc = SortedContainer::default();
+        sc.assign(iter.into_iter().collect());
+        sc
+    }
+}
+
+
+impl<T, C: Compare<T>> Extend<T> for SortedContainer<T, C> {
+
+    /// Inserts all the elements of the iterator
+    /// note: when there are at least as many new elements as
+    /// elements in the container, the new elements are sorted
+    /// (unless they already are) and merged with the current
+    /// ones, and the whole tree is rebuilt (perfectly balanced)
+    /// in linear time; otherwise they are inserted one by one
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut new: Vec<T> = iter.into_iter().collect();
+
+        if new.len() < self.len() {
+            for data in new {
+                self.insert(data);
+            }
+            return;
+        }
+
+        let sorted = new.windows(2).all(|w| self.comparator.compare(&w[0], &w[1]) != Ordering::Greater);
+        if !sorted {
+            new.sort_by(|a, b| self.comparator.compare(a, b));
+        }
+
+        // Merge the two sorted runs: on ties the current elements
+        // come first, so that they are the ones kept in a set and
+        // the oldest occurrences in a multiset
+        let mut current = self.take_all().peekable();
+        let mut new = new.into_iter().peekable();
+        let mut elements: Vec<T> = Vec::with_capacity(current.len() + new.len());
+        loop {
+            let from_current = match (current.peek(), new.peek()) {
+                (Some(a), Some(b)) => self.comparator.compare(a, b) != Ordering::Greater,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            elements.push(if from_current { current.next() } else { new.next() }.unwrap());
+        }
+        self.assign(elements);
+    }
+}
+
+
+// The nodes are destroyed one at a time: the default
+// (recursive) destruction of the links would overflow
+// the stack on very deep trees
+impl<T, C> Drop for SortedContainer<T, C> {
+    fn drop(&mut self) {
+        let mut current = self.root.take();
+
+        while let Some(mut n) = current {
+            match n.left.take() {
+                // Rotate the left child up so that
+                // the tree becomes a right-leaning list
+                Some(mut left) => {
+                    n.left = left.right.take();
+                    left.right = Some(n);
+                    current = Some(left);
+                }
+                // The node has no left child anymore:
+                // it is dropped once detached from its right branch
+                None => current = n.right.take(),
+            }
+        }
+    }
+}
+
+
+/// A borrowing iterator over the elements of a
+/// SortedContainer, visited in ascending order
+/// note: the iterator keeps two stacks of nodes, one for
+/// each end, and stops as soon as all the elements have
+/// been returned so that the two ends never cross
+pub struct Iter<'a, T: 'a> {
+    front: Vec<&'a Node<T>>,
+    back:  Vec<&'a Node<T>>,
+    // Occurrences already returned from the node on
+    // top of each stack (see Node::occurrence)
+    front_done: usize,
+    back_done:  usize,
+    remaining: usize,
+}
+
+
+impl<'a, T> Iter<'a, T> {
+
+    fn new(root: &'a Link<T>) -> Self {
+        let mut iter = Iter {
+            front: Vec::new(), back: Vec::new(),
+            front_done: 0, back_done: 0,
+            remaining: size(root),
+        };
+        iter.push_left(root);
+        iter.push_right(root);
+        iter
+    }
+
+    // Push on the front stack the given node and all
+    // the nodes along its left branch
+    fn push_left(&mut self, mut link: &'a Link<T>) {
+        while let Some(ref n) = *link {
+            self.front.push(n);
+            link = &n.left;
+        }
+    }
+
+    // Push on the back stack the given node and all
+    // the nodes along its right branch
+    fn push_right(&mut self, mut link: &'a Link<T>) {
+        while let Some(ref n) = *link {
+            self.back.push(n);
+            link = &n.right;
+        }
+    }
+}
+
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 { return None; }
+        let n = *self.front.last().unwrap();
+        let data = n.occurrence(self.front_done);
+
+        // Move to the next node after the last occurrence
+        self.front_done += 1;
+        if self.front_done == n.count() {
+            self.front.pop();
+            self.front_done = 0;
+            self.push_left(&n.right);
+        }
+
+        self.remaining -= 1;
+        Some(data)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 { return None; }
+        let n = *self.back.last().unwrap();
+        let data = n.occurrence(n.count() - 1 - self.back_done);
+
+        // Move to the previous node after the first occurrence
+        self.back_done += 1;
+        if self.back_done == n.count() {
+            self.back.pop();
+            self.back_done = 0;
+            self.push_right(&n.left);
+        }
+
+        self.remaining -= 1;
+        Some(data)
+    }
+}
+
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+
+/// An iterator over a sub-range of the elements of a
+/// SortedContainer, visited in ascending order
+/// note: the top of each stack is the next element to
+/// return from that end, so the iteration is over when
+/// both ends have reached the same node and all of its
+/// occurrences have been returned
+pub struct Range<'a, T: 'a> {
+    front: Vec<&'a Node<T>>,
+    back:  Vec<&'a Node<T>>,
+    // Occurrences already returned from the node on
+    // top of each stack (see Node::occurrence)
+    front_done: usize,
+    back_done:  usize,
+}
+
+
+impl<'a, T> Range<'a, T> {
+
+    // Records that an occurrence of the given node (the top
+    // of one of the stacks) has been returned from one end
+    // @return true if the node has no more occurrences
+    //     to return from that end
+    fn consume(&mut self, n: &Node<T>, from_front: bool) -> bool {
+        if from_front { self.front_done += 1; } else { self.back_done += 1; }
+
+        // Both ends on the same node: it is the last
+        // one, the iteration is over with its last occurrence
+        let last = match (self.front.last(), self.back.last()) {
+            (Some(lo), Some(hi)) => ::std::ptr::eq(*lo, *hi),
+            _ => true,
+        };
+        if last {
+            if self.front_done + self.back_done == n.count() {
+                self.front.clear();
+                self.back.clear();
+            }
+            return false;
+        }
+
+        if from_front { self.front_done == n.count() } else { self.back_done == n.count() }
+    }
+}
+
+
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let n = *self.front.last()?;
+        let data = n.occurrence(self.front_done);
+
+        if self.consume(n, true) {
+            self.front.pop();
+            self.front_done = 0;
+
+            // Push the left branch of the right subtree
+            let mut link = &n.right;
+            while let Some(ref m) = *link {
+                self.front.push(m);
+                link = &m.left;
+            }
+        }
+
+        Some(data)
+    }
+}
+
+
+impl<'a, T> DoubleEndedIterator for Range<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        let n = *self.back.last()?;
+        let data = n.occurrence(n.count() - 1 - self.back_done);
+
+        if self.consume(n, false) {
+            self.back.pop();
+            self.back_done = 0;
+
+            // Push the right branch of the left subtree
+            let mut link = &n.left;
+            while let Some(ref m) = *link {
+                self.back.push(m);
+                link = &m.right;
+            }
+        }
+
+        Some(data)
+    }
+}
+
+
+// A linear merge of the in-order traversals of two
+// containers, on which all the set operations are built
+// (the elements are compared with the comparator of the
+// first container)
+struct Merge<'a, T: 'a, C: 'a> {
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Traversal<'a, T>>,
+    comparator: &'a C,
+}
+
+
+impl<'a, T, C: Compare<T>> Merge<'a, T, C> {
+
+    fn new(a: &'a SortedContainer<T, C>, b: &'a SortedContainer<T, C>) -> Self {
+        let b = if same_order::<C>() {
+            Traversal::InOrder(b.iter())
+        } else {
+            // The order of b may not be the one of a
+            let mut elements: Vec<&T> = b.iter().collect();
+            elements.sort_by(|x, y| a.comparator.compare(*x, *y));
+            Traversal::Sorted(elements.into_iter())
+        };
+        Merge { a: a.iter().peekable(), b: b.peekable(), comparator: &a.comparator }
+    }
+
+    // Advances the traversal which is behind, or both of
+    // them if their next elements are equivalent
+    // @return the elements taken from a and from b
+    //     (None, None) once both traversals are over
+    fn step(&mut self) -> (Option<&'a T>, Option<&'a T>) {
+        let order = match (self.a.peek(), self.b.peek()) {
+            (None, None)       => return (None, None),
+            (Some(_), None)    => Ordering::Less,
+            (None, Some(_))    => Ordering::Greater,
+            (Some(x), Some(y)) => self.comparator.compare(*x, *y),
+        };
+        match order {
+            Ordering::Less    => (self.a.next(), None),
+            Ordering::Greater => (None, self.b.next()),
+            Ordering::Equal   => (self.a.next(), self.b.next()),
+        }
+    }
+}
+
+
+// The traversal of the second container of a merge: either
+// its in-order traversal or its elements sorted again with
+// the comparator of the first container
+enum Traversal<'a, T: 'a> {
+    InOrder(Iter<'a, T>),
+    Sorted(::std::vec::IntoIter<&'a T>),
+}
+
+
+impl<'a, T> Iterator for Traversal<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match *self {
+            Traversal::InOrder(ref mut iter) => iter.next(),
+            Traversal::Sorted(ref mut iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            Traversal::InOrder(ref iter) => iter.size_hint(),
+            Traversal::Sorted(ref iter) => iter.size_hint(),
+        }
+    }
+}
+
+
+impl<'a, T> ExactSizeIterator for Traversal<'a, T> {}
+
+
+/// A lazy iterator over the union of two SortedContainers
+/// (see SortedContainer::union)
+pub struct Union<'a, T: 'a, C: 'a = Natural> {
+    merge: Merge<'a, T, C>,
+}
+
+
+impl<'a, T, C: Compare<T>> Iterator for Union<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match self.merge.step() {
+            (Some(x), _) => Some(x),
+            (None, y)    => y,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = (self.merge.a.len(), self.merge.b.len());
+        (a.max(b), Some(a + b))
+    }
+}
+
+
+/// A lazy iterator over the intersection of two
+/// SortedContainers (see SortedContainer::intersection)
+pub struct Intersection<'a, T: 'a, C: 'a = Natural> {
+    merge: Merge<'a, T, C>,
+}
+
+
+impl<'a, T, C: Compare<T>> Iterator for Intersection<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            // Nothing left in common once either side is over
+            if self.merge.a.peek().is_none() || self.merge.b.peek().is_none() {
+                return None;
+            }
+            if let (Some(x), Some(_)) = self.merge.step() {
+                return Some(x);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.merge.a.len().min(self.merge.b.len())))
+    }
+}
+
+
+/// A lazy iterator over the difference of two
+/// SortedContainers (see SortedContainer::difference)
+pub struct Difference<'a, T: 'a, C: 'a = Natural> {
+    merge: Merge<'a, T, C>,
+}
+
+
+impl<'a, T, C: Compare<T>> Iterator for Difference<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.merge.step() {
+                (Some(x), None) => return Some(x),
+                (None, None)    => return None,
+                // The rest of b does not matter once a is over
+                (None, Some(_)) if self.merge.a.peek().is_none() => return None,
+                _ => {}
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.merge.a.len()))
+    }
+}
+
+
+/// A lazy iterator over the symmetric difference of two
+/// SortedContainers (see SortedContainer::symmetric_difference)
+pub struct SymmetricDifference<'a, T: 'a, C: 'a = Natural> {
+    merge: Merge<'a, T, C>,
+}
+
+
+impl<'a, T, C: Compare<T>> Iterator for SymmetricDifference<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.merge.step() {
+                (Some(_), Some(_)) => {}
+                (x, None)          => return x,
+                (None, y)          => return y,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.merge.a.len() + self.merge.b.len()))
+    }
+}
 
-                // Unlink the node from the list
-                let mut leftmost_content = leftmost.take().unwrap();
-                *leftmost = leftmost_content.right.take();
 
-                // Return unlinked data
-                leftmost_content.data
-            };
-            
-            // Put the target (with different data) 
-            // node back on the tree
-            *target = Some(Box::new(target_content));
+/// An owning iterator over the elements of a
+/// SortedContainer, returned in ascending order
+pub struct IntoIter<T> {
+    elements: ::std::vec::IntoIter<T>,
+}
 
-        } else if target_content.left.is_some()  {
-            // Node has only the left child 
-        
-            // Substitute the target with his left child
-            *target = target_content.left;
 
-        } else if target_content.right.is_some() {
-            // Node has only the right child
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
 
-            // Substitute the target with his right child
-            *target = target_content.right;
-        }
+    fn next(&mut self) -> Option<T> {
+        self.elements.next()
     }
 
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.elements.size_hint()
+    }
+}
 
 
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.elements.next_back()
+    }
+}
 
-    // This private function is used internally to find the
-    // position of the node which should contain the specified
-    // data. Note that if such node doesn't exist the returned
-    // link will point to an empty leaf which represent a valid
-    // position for that node (so that it can for example be
-    // inserted there)
-    // @param data: data to search for
-    // @return a mutable reference to a link
-    fn find_pos(&mut self, data: &T) -> &mut Link<T>{
 
-        // Helper function: find specified data recursively
-        fn _find<'a, T: PartialOrd>( current: &'a mut Link<T>, data: &T) -> &'a mut Link<T>{
+impl<T> ExactSizeIterator for IntoIter<T> {}
 
-            enum Direction { Right, Left, Arrived }
 
-            let val = match *current {
-                None => Direction::Arrived,
-                Some(ref n) => {
-                    if      n.data > *data { Direction::Left    }
-                    else if n.data < *data { Direction::Right   }
-                    else                   { Direction::Arrived }
-                }
-            };
+// Moves the elements of a tree in order into
+// a vector while the nodes are freed
+fn into_elements<T>(root: Link<T>) -> Vec<T> {
+    let mut elements = Vec::with_capacity(size(&root));
+    let mut stack: Vec<Box<Node<T>>> = Vec::new();
+    let mut current = root;
 
-            match val {
-                Direction::Left    => _find(&mut current.as_mut().unwrap().left, data),
-                Direction::Right   => _find(&mut current.as_mut().unwrap().right, data),
-                Direction::Arrived => current,
+    loop {
+        // Go down along the left branch detaching the nodes
+        while let Some(mut n) = current {
+            current = n.left.take();
+            stack.push(n);
+        }
+        // Take the smallest node left, then visit its right branch
+        match stack.pop() {
+            None => break,
+            Some(n) => {
+                let n = *n;
+                elements.push(n.data);
+                elements.extend(n.dups);
+                current = n.right;
             }
         }
-
-        // Use helper function
-        _find(&mut self.root, data)
     }
 
+    elements
+}
 
-    /*
-    // This function exists for nothing but illustrative purposes:
-    // it shows a different approach for inserting a node without
-    // the use of the find_pos function
-    fn _insertnode(&self, current: &mut Link<T>, new_node: Node<T>){
-        match current {
-            &mut None => {
-                // Insert Node
-                *current = Some(Box::new(new_node));
-            }
 
-            &mut Some(ref mut n) => {
-                if n.data > new_node.data {
-                    self._insertnode(&mut n.left, new_node);
-                } else if n.data < new_node.data {
-                    self._insertnode(&mut n.right, new_node);
-                } else {
-                    // Node is already present
-                    return;
-                }
-            }
-        }
+impl<T, C> IntoIterator for SortedContainer<T, C> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    /// Consumes the container, moving the elements in
+    /// order into a vector while the nodes are freed
+    fn into_iter(mut self) -> IntoIter<T> {
+        IntoIter { elements: into_elements(self.root.take()).into_iter() }
     }
-    */
+}
 
 
+impl<'a, T, C> IntoIterator for &'a SortedContainer<T, C> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        Iter::new(&self.root)
+    }
 }
 
 
@@ -231,9 +2203,12 @@ impl<T: Display + PartialOrd> SortedContainer<T> {
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
 
-    use sortedcontainer::SortedContainer;
+    use sortedcontainer::{SortedContainer, Balancing, Multiplicity, InvariantError, Link, Node};
+    use std::cmp::Ordering;
+    use std::ops::Bound;
 
 
     /// A basic test testing mostly a normal usage of the public API 
@@ -255,37 +2230,37 @@ mod tests {
 
 
             // The container should contain all the values inserted 
-            assert!(sc.contains(1), "Does not contain value 1");
-            assert!(sc.contains(2), "Does not contain value 2");
-            assert!(sc.contains(3), "Does not contain value 3");
-            assert!(sc.contains(4), "Does not contain value 4");
-            assert!(sc.contains(5), "Does not contain value 5");
-            assert!(sc.contains(6), "Does not contain value 6");
-            assert!(sc.contains(7), "Does not contain value 7");
-            assert!(sc.contains(8), "Does not contain value 8");
-            assert!(sc.contains(9), "Does not contain value 9");
+            assert!(sc.contains(&1), "Does not contain value 1");
+            assert!(sc.contains(&2), "Does not contain value 2");
+            assert!(sc.contains(&3), "Does not contain value 3");
+            assert!(sc.contains(&4), "Does not contain value 4");
+            assert!(sc.contains(&5), "Does not contain value 5");
+            assert!(sc.contains(&6), "Does not contain value 6");
+            assert!(sc.contains(&7), "Does not contain value 7");
+            assert!(sc.contains(&8), "Does not contain value 8");
+            assert!(sc.contains(&9), "Does not contain value 9");
 
 
-            sc.erase(5); sc.erase(3); sc.erase(8);
+            sc.erase(&5); sc.erase(&3); sc.erase(&8);
 
 
             // The container should not contain the erased values
-            assert!(!sc.contains(5), "Contains value 5");
-            assert!(!sc.contains(3), "Contains value 3");
-            assert!(!sc.contains(8), "Contains value 8");
+            assert!(!sc.contains(&5), "Contains value 5");
+            assert!(!sc.contains(&3), "Contains value 3");
+            assert!(!sc.contains(&8), "Contains value 8");
 
 
             // ..but should still contain the value not erased
-            assert!(sc.contains(1), "Does not contain value 1");
-            assert!(sc.contains(2), "Does not contain value 2");
-            assert!(sc.contains(4), "Does not contain value 4");
-            assert!(sc.contains(6), "Does not contain value 6");
-            assert!(sc.contains(7), "Does not contain value 7");
-            assert!(sc.contains(9), "Does not contain value 9");
+            assert!(sc.contains(&1), "Does not contain value 1");
+            assert!(sc.contains(&2), "Does not contain value 2");
+            assert!(sc.contains(&4), "Does not contain value 4");
+            assert!(sc.contains(&6), "Does not contain value 6");
+            assert!(sc.contains(&7), "Does not contain value 7");
+            assert!(sc.contains(&9), "Does not contain value 9");
 
 
-            sc.erase(1); sc.erase(2); sc.erase(4);
-            sc.erase(6); sc.erase(7); sc.erase(9);
+            sc.erase(&1); sc.erase(&2); sc.erase(&4);
+            sc.erase(&6); sc.erase(&7); sc.erase(&9);
 
 
             // After erasing all the values the container should be empty
@@ -303,16 +2278,16 @@ mod tests {
             sc.insert(1); sc.insert(2);
 
             // The container should contain all the values inserted 
-            assert!(sc.contains(1), "Does not contain value 1");
-            assert!(sc.contains(2), "Does not contain value 2");
+            assert!(sc.contains(&1), "Does not contain value 1");
+            assert!(sc.contains(&2), "Does not contain value 2");
 
-            sc.erase(1); sc.erase(1);
+            sc.erase(&1); sc.erase(&1);
 
             // The container should not contain the erased values
-            assert!(!sc.contains(1), "Contains value 1");
+            assert!(!sc.contains(&1), "Contains value 1");
 
             // ..but should still contain the value not erased
-            assert!(sc.contains(2), "Does not contain value 2");
+            assert!(sc.contains(&2), "Does not contain value 2");
     }
 
 
@@ -351,7 +2326,7 @@ mod tests {
                 assert_eq!(right.right.is_none(), true, "Branch should be empty");
             }
 
-            sc.erase(3);
+            sc.erase(&3);
 
             /*
              *  2. Test tree's configuration after deleting 3:
@@ -378,7 +2353,7 @@ mod tests {
     #[test]
     fn test_erase_with_one_child(){
 
-            let mut sc : SortedContainer<u32> = SortedContainer::new();
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::None);
             assert_eq!(sc.root.is_none(), true);
 
             sc.insert(3);
@@ -409,7 +2384,7 @@ mod tests {
                 assert_eq!(left_right.right.is_none(), true, "Branch should be empty");
             }
 
-            sc.erase(1);
+            sc.erase(&1);
 
             /*
              *  2. Test tree's configuration after deleting 1:
@@ -477,7 +2452,7 @@ mod tests {
             }
 
 
-            sc.erase(4);
+            sc.erase(&4);
 
 
             /*
@@ -510,7 +2485,7 @@ mod tests {
             }
 
 
-            sc.erase(2);
+            sc.erase(&2);
 
             
             /*
@@ -595,7 +2570,7 @@ mod tests {
             }
 
             
-            sc.erase(4);
+            sc.erase(&4);
 
             /*
              *  2. Test tree's configuration after deleting 4:
@@ -641,7 +2616,7 @@ mod tests {
     #[test]
     fn test_erase_with_two_children_3(){
 
-            let mut sc : SortedContainer<u32> = SortedContainer::new();
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::None);
             assert_eq!(sc.root.is_none(), true);
 
             sc.insert(8); sc.insert(4); sc.insert(2); 
@@ -696,7 +2671,7 @@ mod tests {
             }
 
 
-            sc.erase(4);
+            sc.erase(&4);
 
             /*
              *  2. Test tree's configuration after deleting 4:
@@ -742,4 +2717,1067 @@ mod tests {
             }
     }
 
+
+
+    // Checks recursively that every node of the subtree satisfies
+    // the AVL property and stores its correct height
+    // @return the height of the subtree
+    fn check_avl<T>(link: &Link<T>) -> usize {
+        match *link {
+            None => 0,
+            Some(ref n) => {
+                let hl = check_avl(&n.left);
+                let hr = check_avl(&n.right);
+                assert!(hl <= hr + 1 && hr <= hl + 1, "Node not balanced");
+                assert_eq!(n.height, 1 + hl.max(hr), "Wrong height");
+                n.height
+            }
+        }
+    }
+
+
+    // Maximum height allowed for an AVL tree of n nodes
+    fn avl_max_height(n: usize) -> usize {
+        (1.45 * ((n + 2) as f64).log2()) as usize
+    }
+
+
+    /// Test that inserting sorted data keeps the height logarithmic
+    #[test]
+    fn test_avl_sorted_insert(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            assert_eq!(sc.height(), 0, "Height of empty tree not 0");
+
+            for i in 1..1024 { sc.insert(i); }
+
+            // A perfectly balanced tree is obtained
+            // when inserting 2^k - 1 sorted elements
+            check_avl(&sc.root);
+            assert_eq!(sc.height(), 10, "Wrong height");
+
+            for i in 1..1024 {
+                assert!(sc.contains(&i), "Does not contain value {}", i);
+            }
+
+            // The same insertions produce a list if no balancing is done
+            let mut unbalanced : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::None);
+            for i in 1..1024 { unbalanced.insert(i); }
+            assert_eq!(unbalanced.height(), 1023, "Wrong height");
+    }
+
+
+    /// Test the height bound after insertions in descending and zig-zag order
+    #[test]
+    fn test_avl_insert_orders(){
+
+            let mut desc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in (0..2000).rev() { desc.insert(i); }
+            check_avl(&desc.root);
+            assert!(desc.height() <= avl_max_height(2000), "Tree too high");
+
+            // Alternate small and large values so that
+            // left-right and right-left cases happen
+            let mut zigzag : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in 0..1000 { zigzag.insert(i); zigzag.insert(3000 - i); }
+            check_avl(&zigzag.root);
+            assert!(zigzag.height() <= avl_max_height(2000), "Tree too high");
+
+            // Duplicates are ignored and do not alter the tree
+            let h = zigzag.height();
+            for i in 0..1000 { zigzag.insert(i); }
+            check_avl(&zigzag.root);
+            assert_eq!(zigzag.height(), h, "Height changed");
+    }
+
+
+    /// Test that the tree stays balanced while erasing elements
+    #[test]
+    fn test_avl_erase(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in 0..2000 { sc.insert(i); }
+
+            // Erase all the odd numbers
+            for i in 0..1000 {
+                sc.erase(&(2 * i + 1));
+                check_avl(&sc.root);
+            }
+            assert!(sc.height() <= avl_max_height(1000), "Tree too high");
+
+            for i in 0..1000 {
+                assert!( sc.contains(&(2 * i)),   "Does not contain value {}", 2 * i);
+                assert!(!sc.contains(&(2 * i + 1)), "Contains value {}", 2 * i + 1);
+            }
+
+            // Erase the lower half, leaving only the right part of the tree
+            for i in 0..500 { sc.erase(&(2 * i)); }
+            check_avl(&sc.root);
+            assert!(sc.height() <= avl_max_height(500), "Tree too high");
+
+            for i in 500..1000 { sc.erase(&(2 * i)); }
+            assert_eq!(sc.root.is_none(), true, "Root not empty");
+            assert_eq!(sc.height(), 0, "Height of empty tree not 0");
+    }
+
+
+    /// Test the in-order iteration from both ends
+    #[test]
+    fn test_iter(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::new();
+            assert_eq!(sc.iter().next(), None, "Empty container not empty");
+            assert!(sc.is_empty(), "Container not empty");
+
+            sc.insert(5); sc.insert(2); sc.insert(7);
+            sc.insert(4); sc.insert(1); sc.insert(3);
+            sc.insert(6); sc.insert(9); sc.insert(8);
+            sc.insert(4); // duplicate
+            assert_eq!(sc.len(), 9, "Wrong number of elements");
+
+            let forward: Vec<u32> = sc.iter().cloned().collect();
+            assert_eq!(forward, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+            let backward: Vec<u32> = sc.iter().rev().cloned().collect();
+            assert_eq!(backward, vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+
+            // Alternating between the two ends never
+            // returns the same element twice
+            let mut it = sc.iter();
+            assert_eq!(it.len(), 9);
+            assert_eq!(it.next(),      Some(&1));
+            assert_eq!(it.next_back(), Some(&9));
+            assert_eq!(it.next_back(), Some(&8));
+            assert_eq!(it.next(),      Some(&2));
+            assert_eq!(it.len(), 5);
+            let middle: Vec<u32> = it.cloned().collect();
+            assert_eq!(middle, vec![3, 4, 5, 6, 7]);
+
+            let mut it = sc.iter();
+            for _ in 0..4 { it.next(); it.next_back(); }
+            assert_eq!(it.next_back(), Some(&5));
+            assert_eq!(it.next(), None);
+            assert_eq!(it.next_back(), None);
+
+            sc.erase(&5); sc.erase(&1); sc.erase(&10);
+            assert_eq!(sc.len(), 7, "Wrong number of elements");
+
+            let mut sum = 0;
+            for x in &sc { sum += *x; }
+            assert_eq!(sum, 2 + 3 + 4 + 6 + 7 + 8 + 9);
+    }
+
+
+    /// Test the owning iterator on a balanced tree
+    #[test]
+    fn test_into_iter(){
+
+            let mut sc : SortedContainer<String> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in (0..100).rev() { sc.insert(format!("{:03}", i)); }
+
+            let forward: Vec<String> = sc.into_iter().collect();
+            let expected: Vec<String> = (0..100).map(|i| format!("{:03}", i)).collect();
+            assert_eq!(forward, expected);
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in 0..100 { sc.insert(i); }
+
+            let mut it = sc.into_iter();
+            assert_eq!(it.len(), 100);
+            assert_eq!(it.next_back(), Some(99));
+            assert_eq!(it.next(), Some(0));
+            let backward: Vec<u32> = it.rev().collect();
+            let expected: Vec<u32> = (1..99).rev().collect();
+            assert_eq!(backward, expected);
+    }
+
+
+    /// Test range queries with the different kinds of bounds
+    #[test]
+    fn test_range(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in 0..50 { sc.insert(2 * i); } // even numbers from 0 to 98
+
+            let r: Vec<u32> = sc.range(10..20).cloned().collect();
+            assert_eq!(r, vec![10, 12, 14, 16, 18]);
+
+            let r: Vec<u32> = sc.range(9..=20).cloned().collect();
+            assert_eq!(r, vec![10, 12, 14, 16, 18, 20]);
+
+            let r: Vec<u32> = sc.range(..5).cloned().collect();
+            assert_eq!(r, vec![0, 2, 4]);
+
+            let r: Vec<u32> = sc.range(93..).cloned().collect();
+            assert_eq!(r, vec![94, 96, 98]);
+
+            assert_eq!(sc.range(..).count(), 50);
+
+            // Empty ranges
+            assert_eq!(sc.range(11..12).next(), None);
+            assert_eq!(sc.range(100..).next(), None);
+            assert_eq!(sc.range((Bound::Included(30), Bound::Excluded(20))).next(), None);
+            assert_eq!(sc.range(20..20).next(), None);
+
+            // Single element
+            let r: Vec<u32> = sc.range(20..=20).cloned().collect();
+            assert_eq!(r, vec![20]);
+            let r: Vec<u32> = sc.range(20..=20).rev().cloned().collect();
+            assert_eq!(r, vec![20]);
+
+            // Iteration from both ends
+            let r: Vec<u32> = sc.range(10..=20).rev().cloned().collect();
+            assert_eq!(r, vec![20, 18, 16, 14, 12, 10]);
+
+            let mut it = sc.range(11..19);
+            assert_eq!(it.next(),      Some(&12));
+            assert_eq!(it.next_back(), Some(&18));
+            assert_eq!(it.next_back(), Some(&16));
+            assert_eq!(it.next(),      Some(&14));
+            assert_eq!(it.next(),      None);
+            assert_eq!(it.next_back(), None);
+
+            // Compare against a linear scan on an unbalanced tree
+            let mut unbalanced : SortedContainer<u32> = SortedContainer::new();
+            for i in [50, 20, 80, 10, 30, 70, 90, 25, 35, 75].iter() { unbalanced.insert(*i); }
+            for lo in 0..100 {
+                for hi in lo..100 {
+                    let expected: Vec<u32> = unbalanced.iter().cloned()
+                        .filter(|x| *x >= lo && *x < hi).collect();
+                    let r: Vec<u32> = unbalanced.range(lo..hi).cloned().collect();
+                    assert_eq!(r, expected, "Wrong range {}..{}", lo, hi);
+                }
+            }
+    }
+
+
+    /// Test the lookups through a borrowed key
+    #[test]
+    fn test_get(){
+
+            let mut sc : SortedContainer<String> = SortedContainer::with_balancing(Balancing::Avl);
+            sc.insert("bob".to_string());
+            sc.insert("alice".to_string());
+            sc.insert("carol".to_string());
+
+            // A String can be looked up with a &str
+            assert!(sc.contains("alice"), "Does not contain alice");
+            assert!(!sc.contains("dave"), "Contains dave");
+            assert_eq!(sc.get("bob"), Some(&"bob".to_string()));
+            assert_eq!(sc.get("dave"), None);
+
+            let r: Vec<&String> = sc.range::<str, _>((Bound::Excluded("alice"), Bound::Unbounded)).collect();
+            assert_eq!(r, vec!["bob", "carol"]);
+
+            // Lookups only need a shared reference
+            let shared = &sc;
+            assert!(shared.contains("carol") && shared.contains(&"carol".to_string()));
+    }
+
+
+    /// Test the values returned by insert and erase
+    #[test]
+    fn test_insert_erase_results(){
+
+            let mut sc : SortedContainer<String> = SortedContainer::with_balancing(Balancing::Avl);
+
+            assert_eq!(sc.insert("b".to_string()), None);
+            assert_eq!(sc.insert("a".to_string()), None);
+            assert_eq!(sc.insert("c".to_string()), None);
+            assert_eq!(sc.insert("d".to_string()), None);
+
+            // Duplicates are given back
+            assert_eq!(sc.insert("a".to_string()), Some("a".to_string()));
+            assert_eq!(sc.len(), 4);
+
+            // Erase a leaf, a node with one child and one with two children
+            assert_eq!(sc.erase("d"), Some("d".to_string()));
+            assert_eq!(sc.erase("d"), None);
+            assert_eq!(sc.insert("d".to_string()), None);
+            assert_eq!(sc.erase("c"), Some("c".to_string()));
+            assert_eq!(sc.erase("b"), Some("b".to_string()));
+            assert_eq!(sc.erase("x"), None);
+            assert_eq!(sc.len(), 2);
+
+            let left: Vec<&String> = sc.iter().collect();
+            assert_eq!(left, vec!["a", "d"]);
+    }
+
+
+    // Builds the degenerate tree obtained by inserting the values
+    // from 0 to n-1 in ascending order without balancing (every node
+    // only has a right child). The list is built by hand since each
+    // insertion would otherwise walk down the whole list.
+    fn sorted_chain(n: u32) -> SortedContainer<u32> {
+        let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::None);
+        for i in (0..n).rev() {
+            let mut node = Node::new(i);
+            node.right = sc.root.take();
+            node.height = (n - i) as usize;
+            node.size = (n - i) as usize;
+            sc.root = Some(Box::new(node));
+        }
+        sc
+    }
+
+
+    /// Test that a very deep tree can be used and dropped
+    /// without overflowing the stack
+    #[test]
+    fn test_deep_chain(){
+
+            const N: u32 = 1_000_000;
+
+            let mut sc = sorted_chain(N);
+            assert_eq!(sc.height(), N as usize, "Wrong height");
+
+            assert!(sc.contains(&(N - 1)), "Does not contain the last value");
+            assert!(!sc.contains(&N), "Contains a value never inserted");
+            assert_eq!(sc.get(&(N / 2)), Some(&(N / 2)));
+
+            assert_eq!(sc.iter().count(), N as usize);
+            assert_eq!(sc.iter().next_back(), Some(&(N - 1)));
+            assert_eq!(sc.range(N - 10..).count(), 10);
+
+            // Insert and erase at the bottom of the list
+            assert_eq!(sc.insert(N), None);
+            assert_eq!(sc.insert(N), Some(N));
+            assert_eq!(sc.height(), N as usize + 1, "Wrong height");
+            assert_eq!(sc.erase(&(N - 1)), Some(N - 1));
+            assert_eq!(sc.erase(&(N - 1)), None);
+            assert_eq!(sc.height(), N as usize, "Wrong height");
+
+            // Erase the root (it only has a right child)
+            assert_eq!(sc.erase(&0), Some(0));
+            assert_eq!(sc.len(), N as usize - 1);
+            assert_eq!(sc.iter().next(), Some(&1));
+
+            drop(sc);
+
+            // Consuming the list
+            let sc = sorted_chain(N);
+            assert_eq!(sc.into_iter().rev().take_while(|x| *x > 0).count(), N as usize - 1);
+    }
+
+
+    /// Test the occurrences counting of a multiset
+    #[test]
+    fn test_multiset(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            assert_eq!(sc.multiplicity(), Multiplicity::Multiset);
+
+            for i in [3, 1, 2, 3, 3, 1, 5].iter() {
+                assert_eq!(sc.insert(*i), None, "Value {} rejected", i);
+            }
+
+            assert_eq!(sc.len(), 7, "Wrong number of elements");
+            assert_eq!(sc.count(&3), 3);
+            assert_eq!(sc.count(&1), 2);
+            assert_eq!(sc.count(&4), 0);
+
+            // The iteration repeats the elements
+            let forward: Vec<u32> = sc.iter().cloned().collect();
+            assert_eq!(forward, vec![1, 1, 2, 3, 3, 3, 5]);
+            let backward: Vec<u32> = sc.iter().rev().cloned().collect();
+            assert_eq!(backward, vec![5, 3, 3, 3, 2, 1, 1]);
+
+            let mut it = sc.iter();
+            assert_eq!(it.next_back(), Some(&5));
+            assert_eq!(it.next_back(), Some(&3));
+            assert_eq!(it.next(), Some(&1));
+            assert_eq!(it.next(), Some(&1));
+            assert_eq!(it.next(), Some(&2));
+            assert_eq!(it.next(), Some(&3));
+            assert_eq!(it.next_back(), Some(&3));
+            assert_eq!(it.next(), None);
+
+            let r: Vec<u32> = sc.range(2..=3).cloned().collect();
+            assert_eq!(r, vec![2, 3, 3, 3]);
+            let r: Vec<u32> = sc.range(3..=3).rev().cloned().collect();
+            assert_eq!(r, vec![3, 3, 3]);
+            let mut it = sc.range(3..4);
+            assert_eq!(it.next(), Some(&3));
+            assert_eq!(it.next_back(), Some(&3));
+            assert_eq!(it.next(), Some(&3));
+            assert_eq!(it.next_back(), None);
+
+            // Erasing one occurrence at a time
+            assert_eq!(sc.erase_one(&3), Some(3));
+            assert_eq!(sc.count(&3), 2);
+            assert_eq!(sc.erase(&1), Some(1));
+            assert_eq!(sc.erase(&1), Some(1));
+            assert_eq!(sc.erase(&1), None);
+            assert_eq!(sc.count(&1), 0);
+            assert_eq!(sc.len(), 4);
+
+            // Erasing all the occurrences at once
+            assert_eq!(sc.erase_all(&3), vec![3, 3]);
+            assert_eq!(sc.erase_all(&3), Vec::<u32>::new());
+            assert_eq!(sc.len(), 2);
+
+            let left: Vec<u32> = sc.into_iter().collect();
+            assert_eq!(left, vec![2, 5]);
+    }
+
+
+    /// Test that the occurrences follow their node when a
+    /// node with two children is erased
+    #[test]
+    fn test_multiset_erase_two_children(){
+
+            let mut sc : SortedContainer<String> = SortedContainer::with_policy(Balancing::None, Multiplicity::Multiset);
+
+            for s in ["b", "a", "d", "c", "e", "c", "d"].iter() { sc.insert(s.to_string()); }
+
+            // "b" has two children: it is replaced by its successor "c"
+            assert_eq!(sc.erase_all("b"), vec!["b".to_string()]);
+            assert_eq!(sc.root.as_ref().unwrap().data, "c");
+            assert_eq!(sc.count("c"), 2);
+            assert_eq!(sc.count("d"), 2);
+
+            let all: Vec<String> = sc.into_iter().collect();
+            assert_eq!(all, vec!["a", "c", "c", "d", "d", "e"]);
+
+            // A set still rejects the duplicates
+            let mut set : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Set);
+            assert_eq!(set.insert(1), None);
+            assert_eq!(set.insert(1), Some(1));
+            assert_eq!(set.count(&1), 1);
+            assert_eq!(set.erase_all(&1), vec![1]);
+    }
+
+
+    // Checks recursively that every node of the subtree
+    // stores the number of elements it contains
+    // @return the size of the subtree
+    fn check_sizes<T>(link: &Link<T>) -> usize {
+        match *link {
+            None => 0,
+            Some(ref n) => {
+                let size = check_sizes(&n.left) + n.count() + check_sizes(&n.right);
+                assert_eq!(n.size, size, "Wrong size");
+                size
+            }
+        }
+    }
+
+
+    /// Test the order statistics of the container
+    #[test]
+    fn test_select_rank(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            assert_eq!(sc.select(0), None);
+            assert_eq!(sc.median(), None);
+            assert_eq!(sc.rank(&5), 0);
+
+            for i in 0..100 { sc.insert(10 * i); } // 0, 10, ..., 990
+            check_sizes(&sc.root);
+
+            for i in 0..100 {
+                assert_eq!(sc.select(i), Some(&(10 * i as u32)));
+                assert_eq!(sc.rank(&(10 * i as u32)), i);
+                assert_eq!(sc.rank(&(10 * i as u32 + 5)), i + 1);
+            }
+            assert_eq!(sc.select(100), None);
+            assert_eq!(sc.median(), Some(&490));
+
+            sc.insert(1000);
+            assert_eq!(sc.median(), Some(&500));
+
+            // Each occurrence counts in a multiset
+            let mut ms : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            for i in [1, 2, 2, 2, 3, 5, 5].iter() { ms.insert(*i); }
+            check_sizes(&ms.root);
+            assert_eq!(ms.len(), 7);
+            let selected: Vec<u32> = (0..7).map(|k| *ms.select(k).unwrap()).collect();
+            assert_eq!(selected, vec![1, 2, 2, 2, 3, 5, 5]);
+            assert_eq!(ms.rank(&2), 1);
+            assert_eq!(ms.rank(&3), 4);
+            assert_eq!(ms.rank(&4), 5);
+            assert_eq!(ms.median(), Some(&2));
+
+            ms.erase_one(&2);
+            check_sizes(&ms.root);
+            assert_eq!(ms.rank(&3), 3);
+            ms.erase_all(&5);
+            check_sizes(&ms.root);
+            assert_eq!(ms.len(), 4);
+            assert_eq!(ms.select(3), Some(&3));
+    }
+
+
+    /// Test that the sizes stay correct when the erased node
+    /// has two children and is replaced by its successor
+    #[test]
+    fn test_sizes_erase_two_children(){
+
+            for balancing in [Balancing::None, Balancing::Avl].iter() {
+                let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(*balancing);
+                for i in [8, 4, 2, 1, 3, 6, 5, 7, 12, 10, 14, 9, 11, 13, 15].iter() { sc.insert(*i); }
+                check_sizes(&sc.root);
+
+                // Erase the inner nodes first, then everything else
+                for i in [4, 8, 12, 6, 10, 2, 14, 1, 3, 5, 7, 9, 11, 13, 15].iter() {
+                    sc.erase(i);
+                    check_sizes(&sc.root);
+                    let elements: Vec<u32> = sc.iter().cloned().collect();
+                    for (k, e) in elements.iter().enumerate() {
+                        assert_eq!(sc.select(k), Some(e));
+                        assert_eq!(sc.rank(e), k);
+                    }
+                }
+                assert_eq!(sc.len(), 0);
+            }
+    }
+
+
+    /// Test the nearest-neighbour queries
+    #[test]
+    fn test_nearest(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            assert_eq!(sc.first(), None);
+            assert_eq!(sc.last(), None);
+            assert_eq!(sc.floor(&5), None);
+            assert_eq!(sc.ceiling(&5), None);
+            assert_eq!(sc.pop_first(), None);
+            assert_eq!(sc.pop_last(), None);
+
+            for i in [40, 20, 60, 10, 30, 50, 70].iter() { sc.insert(*i); }
+            assert_eq!(sc.first(), Some(&10));
+            assert_eq!(sc.last(), Some(&70));
+
+            assert_eq!(sc.floor(&30), Some(&30));
+            assert_eq!(sc.floor(&35), Some(&30));
+            assert_eq!(sc.floor(&5), None);
+            assert_eq!(sc.ceiling(&30), Some(&30));
+            assert_eq!(sc.ceiling(&35), Some(&40));
+            assert_eq!(sc.ceiling(&75), None);
+            assert_eq!(sc.predecessor(&30), Some(&20));
+            assert_eq!(sc.predecessor(&35), Some(&30));
+            assert_eq!(sc.predecessor(&10), None);
+            assert_eq!(sc.successor(&30), Some(&40));
+            assert_eq!(sc.successor(&25), Some(&30));
+            assert_eq!(sc.successor(&70), None);
+
+            // Popping from both ends keeps the tree balanced
+            assert_eq!(sc.pop_first(), Some(10));
+            check_avl(&sc.root);
+            check_sizes(&sc.root);
+            assert_eq!(sc.pop_last(), Some(70));
+            check_avl(&sc.root);
+            check_sizes(&sc.root);
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), vec![20, 30, 40, 50, 60]);
+            while sc.pop_first().is_some() {
+                check_avl(&sc.root);
+            }
+            assert_eq!(sc.len(), 0);
+
+            // In a multiset only one occurrence is popped
+            let mut ms : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            for i in [2, 1, 1, 3, 3].iter() { ms.insert(*i); }
+            assert_eq!(ms.pop_first(), Some(1));
+            assert_eq!(ms.pop_last(), Some(3));
+            assert_eq!(ms.iter().cloned().collect::<Vec<u32>>(), vec![1, 2, 3]);
+            check_sizes(&ms.root);
+    }
+
+
+    /// Test the set operations, both on sets and on multisets
+    #[test]
+    fn test_set_algebra(){
+
+            let mut a : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            let mut b : SortedContainer<u32> = SortedContainer::new();
+            for i in [5, 1, 3, 7, 9].iter() { a.insert(*i); }
+            for i in [4, 3, 8, 9, 2, 10].iter() { b.insert(*i); }
+
+            let collect = |it: &mut dyn Iterator<Item = &u32>| it.cloned().collect::<Vec<u32>>();
+            assert_eq!(collect(&mut a.union(&b)), vec![1, 2, 3, 4, 5, 7, 8, 9, 10]);
+            assert_eq!(collect(&mut a.intersection(&b)), vec![3, 9]);
+            assert_eq!(collect(&mut a.difference(&b)), vec![1, 5, 7]);
+            assert_eq!(collect(&mut b.difference(&a)), vec![2, 4, 8, 10]);
+            assert_eq!(collect(&mut a.symmetric_difference(&b)), vec![1, 2, 4, 5, 7, 8, 10]);
+            assert_eq!(a.is_subset(&b), false);
+            assert_eq!(a.is_disjoint(&b), false);
+
+            // Operations involving an empty container
+            let empty : SortedContainer<u32> = SortedContainer::new();
+            assert_eq!(collect(&mut a.union(&empty)), vec![1, 3, 5, 7, 9]);
+            assert_eq!(collect(&mut empty.union(&a)), vec![1, 3, 5, 7, 9]);
+            assert_eq!(a.intersection(&empty).next(), None);
+            assert_eq!(collect(&mut a.difference(&empty)), vec![1, 3, 5, 7, 9]);
+            assert_eq!(empty.difference(&a).next(), None);
+            assert_eq!(empty.is_subset(&a), true);
+            assert_eq!(empty.is_disjoint(&a), true);
+
+            let mut c : SortedContainer<u32> = SortedContainer::new();
+            for i in [3, 9].iter() { c.insert(*i); }
+            assert_eq!(c.is_subset(&a), true);
+            assert_eq!(c.is_subset(&b), true);
+            assert_eq!(a.is_subset(&a), true);
+            c.insert(4);
+            assert_eq!(c.is_subset(&a), false);
+            c.erase(&3);
+            c.erase(&9);
+            assert_eq!(c.is_disjoint(&a), true);
+
+            // Multisets: occurrences are matched one by one
+            let mut ma : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            let mut mb : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            for i in [1, 1, 1, 2, 3, 3].iter() { ma.insert(*i); }
+            for i in [1, 2, 2, 3, 3, 3].iter() { mb.insert(*i); }
+            assert_eq!(collect(&mut ma.union(&mb)), vec![1, 1, 1, 2, 2, 3, 3, 3]);
+            assert_eq!(collect(&mut ma.intersection(&mb)), vec![1, 2, 3, 3]);
+            assert_eq!(collect(&mut ma.difference(&mb)), vec![1, 1]);
+            assert_eq!(collect(&mut ma.symmetric_difference(&mb)), vec![1, 1, 2, 3]);
+            assert_eq!(ma.is_subset(&mb), false);
+    }
+
+
+    /// Test the construction of balanced trees from
+    /// sorted and unsorted sequences
+    #[test]
+    fn test_from_sorted_iter(){
+
+            // A sorted sequence gives a perfectly balanced tree
+            for n in 0..70u32 {
+                let sc = SortedContainer::from_sorted_iter(0..n);
+                assert_eq!(sc.len(), n as usize);
+                check_sizes(&sc.root);
+                check_avl(&sc.root);
+                let mut perfect = 0;
+                while (1 << perfect) <= n { perfect += 1; }
+                assert_eq!(sc.height(), perfect);
+                assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), (0..n).collect::<Vec<u32>>());
+            }
+
+            // A big sorted sequence does not produce a chain
+            let sc: SortedContainer<u32> = (0..1_000_000).collect();
+            assert_eq!(sc.height(), 20);
+            assert_eq!(sc.select(123_456), Some(&123_456));
+
+            // Unsorted input is sorted and deduplicated
+            let sc: SortedContainer<u32> = vec![5, 3, 9, 3, 1, 5, 7].into_iter().collect();
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), vec![1, 3, 5, 7, 9]);
+            check_sizes(&sc.root);
+            check_avl(&sc.root);
+
+            // A multiset keeps all the occurrences
+            let mut ms : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            ms.extend(vec![2, 1, 2, 3, 2]);
+            assert_eq!(ms.iter().cloned().collect::<Vec<u32>>(), vec![1, 2, 2, 2, 3]);
+            assert_eq!(ms.count(&2), 3);
+            check_sizes(&ms.root);
+    }
+
+
+    /// Test extending a container which already has elements
+    #[test]
+    fn test_extend(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            for i in [10, 20, 30].iter() { sc.insert(*i); }
+
+            // Few new elements: they are inserted one by one
+            sc.extend(vec![25, 10]);
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), vec![10, 20, 25, 30]);
+            check_avl(&sc.root);
+
+            // Many new elements: the tree is rebuilt
+            sc.extend(0..40);
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), (0..40).collect::<Vec<u32>>());
+            assert_eq!(sc.balancing(), Balancing::Avl);
+            check_avl(&sc.root);
+            check_sizes(&sc.root);
+            assert_eq!(sc.height(), 6);
+
+            // A multiset keeps the occurrences already present
+            let mut ms : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            ms.insert(1);
+            ms.extend(vec![1, 0]);
+            assert_eq!(ms.iter().cloned().collect::<Vec<u32>>(), vec![0, 1, 1]);
+            check_sizes(&ms.root);
+
+            // Unsorted new elements interleaving with the current
+            // ones are merged after them among equivalent elements
+            let mut pairs = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Multiset,
+                |a: &(u32, char), b: &(u32, char)| a.0.cmp(&b.0));
+            for pair in [(1, 'a'), (3, 'a'), (5, 'a')].iter() { pairs.insert(*pair); }
+            pairs.extend(vec![(6, 'b'), (3, 'b'), (0, 'b'), (1, 'b')]);
+            assert_eq!(pairs.iter().cloned().collect::<Vec<(u32, char)>>(),
+                       vec![(0, 'b'), (1, 'a'), (1, 'b'), (3, 'a'), (3, 'b'), (5, 'a'), (6, 'b')]);
+            assert!(pairs.validate().is_ok());
+    }
+
+
+    // Helper function: checks that the elements of the
+    // tree are in ascending order, and the tree shape
+    fn check_tree(sc: &SortedContainer<u32>) -> Vec<u32> {
+        let elements: Vec<u32> = sc.iter().cloned().collect();
+        assert!(elements.windows(2).all(|w| w[0] < w[1]), "Elements not sorted");
+        assert_eq!(check_sizes(&sc.root), elements.len());
+        if sc.balancing() == Balancing::Avl { check_avl(&sc.root); }
+        assert_eq!(sc.validate(), Ok(()));
+        elements
+    }
+
+
+    /// Test splitting a container at every possible key
+    /// and joining the two parts back
+    #[test]
+    fn test_split_off_append(){
+
+            for balancing in [Balancing::None, Balancing::Avl].iter() {
+                for n in 0..40u32 {
+                    for key in 0..(2 * n + 2) {
+                        // Odd elements only, so that keys both present and
+                        // absent are tried, inserted from both ends in turn
+                        let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(*balancing);
+                        for i in 0..n {
+                            let j = if i % 2 == 0 { i / 2 } else { n - 1 - i / 2 };
+                            sc.insert(j * 2 + 1);
+                        }
+
+                        let mut high = sc.split_off(&key);
+                        let low_elements = check_tree(&sc);
+                        let high_elements = check_tree(&high);
+                        assert!(low_elements.iter().all(|e| *e < key));
+                        assert!(high_elements.iter().all(|e| *e >= key));
+                        assert_eq!(low_elements.len() + high_elements.len(), n as usize);
+
+                        sc.append(&mut high);
+                        assert_eq!(high.len(), 0);
+                        assert_eq!(check_tree(&sc), (0..n).map(|i| i * 2 + 1).collect::<Vec<u32>>());
+                    }
+                }
+            }
+    }
+
+
+    /// Test splitting and joining big AVL trees
+    /// of very different heights
+    #[test]
+    fn test_split_off_append_big(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            sc.extend(0..100_000);
+
+            let mut high = sc.split_off(&99_990);
+            assert_eq!(high.len(), 10);
+            let mut middle = sc.split_off(&10);
+            assert_eq!(sc.len(), 10);
+            check_tree(&sc);
+            check_tree(&middle);
+            check_tree(&high);
+
+            // Join the small trees at both ends of the big one
+            middle.append(&mut high);
+            middle.append(&mut sc);
+            assert_eq!(check_tree(&middle), (0..100_000).collect::<Vec<u32>>());
+            assert!(middle.height() <= avl_max_height(100_000));
+    }
+
+
+    /// Test appending containers whose elements overlap
+    #[test]
+    fn test_append_overlapping(){
+
+            let mut a : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            let mut b : SortedContainer<u32> = SortedContainer::new();
+            for i in [1, 5, 9].iter() { a.insert(*i); }
+            for i in [9, 4, 3, 2, 1, 0].iter() { b.insert(*i); }
+            a.append(&mut b);
+            assert_eq!(check_tree(&a), vec![0, 1, 2, 3, 4, 5, 9]);
+            assert_eq!(b.is_empty(), true);
+
+            // Multisets keep all the occurrences, and splitting
+            // never separates the occurrences of an element
+            let mut ma : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            let mut mb : SortedContainer<u32> = SortedContainer::with_policy(Balancing::Avl, Multiplicity::Multiset);
+            for i in [1, 2, 2, 3].iter() { ma.insert(*i); }
+            for i in [3, 3, 4].iter() { mb.insert(*i); }
+            ma.append(&mut mb);
+            assert_eq!(ma.iter().cloned().collect::<Vec<u32>>(), vec![1, 2, 2, 3, 3, 3, 4]);
+            let high = ma.split_off(&3);
+            assert_eq!(ma.iter().cloned().collect::<Vec<u32>>(), vec![1, 2, 2]);
+            assert_eq!(high.iter().cloned().collect::<Vec<u32>>(), vec![3, 3, 3, 4]);
+            check_sizes(&ma.root);
+            check_sizes(&high.root);
+            check_avl(&high.root);
+    }
+
+
+    /// Test that the validation detects every kind of
+    /// corruption of the tree
+    #[test]
+    fn test_validate(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            assert_eq!(sc.validate(), Ok(()));
+            for i in [4, 2, 6, 1, 3, 5, 7].iter() { sc.insert(*i); }
+            assert_eq!(sc.validate(), Ok(()));
+
+            // Swap two elements
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().data = 5;
+            sc.root.as_mut().unwrap().right.as_mut().unwrap().data = 2;
+            assert_eq!(sc.validate(), Err(InvariantError::Unordered{position: 2}));
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().data = 1;
+            assert_eq!(sc.validate(), Err(InvariantError::Duplicate{position: 1}));
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().data = 2;
+            sc.root.as_mut().unwrap().right.as_mut().unwrap().data = 6;
+            assert_eq!(sc.validate(), Ok(()));
+
+            // Corrupt the metadata
+            sc.root.as_mut().unwrap().height = 2;
+            assert_eq!(sc.validate(), Err(InvariantError::WrongHeight{position: 3, stored: 2, actual: 3}));
+            sc.root.as_mut().unwrap().height = 3;
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().left.as_mut().unwrap().size = 2;
+            assert_eq!(sc.validate(), Err(InvariantError::WrongSize{position: 0, stored: 2, actual: 1}));
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().left.as_mut().unwrap().size = 1;
+
+            // Duplicates in a set
+            sc.root.as_mut().unwrap().dups.push(4);
+            sc.root.as_mut().unwrap().size += 1;
+            assert_eq!(sc.validate(), Err(InvariantError::Duplicate{position: 4}));
+            sc.multiplicity = Multiplicity::Multiset;
+            assert_eq!(sc.validate(), Ok(()));
+            sc.root.as_mut().unwrap().dups.push(8);
+            sc.root.as_mut().unwrap().size += 1;
+            assert_eq!(sc.validate(), Err(InvariantError::MisplacedOccurrence{position: 3}));
+
+            // A chain is a valid tree, but not a valid AVL tree
+            let mut chain = sorted_chain(3);
+            assert_eq!(chain.validate(), Ok(()));
+            chain.balancing = Balancing::Avl;
+            assert_eq!(chain.validate(), Err(InvariantError::Unbalanced{position: 0, balance_factor: -2}));
+            assert_eq!(
+                format!("{}", chain.validate().unwrap_err()),
+                "node at 0 has balance factor -2"
+            );
+    }
+
+
+    /// Test that the validation performed after every mutation
+    /// does not fail when the tree is being modified normally
+    #[test]
+    fn test_validation_after_mutations(){
+
+            for balancing in [Balancing::None, Balancing::Avl].iter() {
+                let mut sc : SortedContainer<u32> = SortedContainer::with_policy(*balancing, Multiplicity::Multiset);
+                sc.set_validation(true);
+                for i in 0..200u32 { sc.insert(i * 37 % 101); }
+                let mut high = sc.split_off(&50);
+                sc.append(&mut high);
+                for i in 0..100u32 { sc.erase(&(i * 13 % 101)); }
+                while sc.pop_first().is_some() {}
+                sc.extend(0..100);
+                sc.check_invariants();
+            }
+    }
+
+
+    /// Test that a corrupted tree is detected
+    /// by the next mutation in debug builds
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid SortedContainer: node at 0 has height 5 instead of 1")]
+    fn test_validation_panics(){
+
+            let mut sc : SortedContainer<u32> = SortedContainer::with_balancing(Balancing::Avl);
+            sc.set_validation(true);
+            for i in 0..10u32 { sc.insert(i); }
+            sc.root.as_mut().unwrap().left.as_mut().unwrap().left.as_mut().unwrap().height = 5;
+            sc.insert(20);
+    }
+
+
+    // A floating point number ordered with f64::total_cmp,
+    // where NaN is bigger than every other number
+    #[derive(Debug, Clone, Copy)]
+    struct Float(f64);
+
+    impl PartialEq for Float {
+        fn eq(&self, other: &Float) -> bool { self.0.total_cmp(&other.0) == Ordering::Equal }
+    }
+
+    impl Eq for Float {}
+
+    impl PartialOrd for Float {
+        fn partial_cmp(&self, other: &Float) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+
+    impl Ord for Float {
+        fn cmp(&self, other: &Float) -> Ordering { self.0.total_cmp(&other.0) }
+    }
+
+
+    /// Test that values which are not comparable with
+    /// PartialOrd (NaN) are stored as distinct elements
+    /// once they are given a total order
+    #[test]
+    fn test_total_order(){
+
+            let mut sc : SortedContainer<Float> = SortedContainer::with_balancing(Balancing::Avl);
+            for x in [1.5, f64::NAN, -2.0, 0.0, f64::INFINITY].iter() { sc.insert(Float(*x)); }
+            assert_eq!(sc.len(), 5);
+            assert_eq!(sc.validate(), Ok(()));
+            assert_eq!(sc.contains(&Float(f64::NAN)), true);
+            assert_eq!(sc.contains(&Float(2.0)), false);
+            assert!(sc.last().unwrap().0.is_nan());
+            assert_eq!(sc.first(), Some(&Float(-2.0)));
+
+            assert!(sc.insert(Float(f64::NAN)).is_some());
+            assert!(sc.erase(&Float(f64::NAN)).is_some());
+            assert_eq!(sc.last(), Some(&Float(f64::INFINITY)));
+    }
+
+
+    /// Test containers ordered by a custom comparator
+    #[test]
+    fn test_comparator(){
+
+            // Descending order
+            let mut sc = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set,
+                                                          |a: &u32, b: &u32| b.cmp(a));
+            for i in [5, 1, 9, 3, 7].iter() { sc.insert(*i); }
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), vec![9, 7, 5, 3, 1]);
+            assert_eq!(sc.contains(&3), true);
+            assert_eq!(sc.contains(&4), false);
+            assert_eq!(sc.range((Bound::Included(7), Bound::Included(3))).cloned().collect::<Vec<u32>>(), vec![7, 5, 3]);
+            assert_eq!(sc.first(), Some(&9));
+            assert_eq!(sc.floor(&4), Some(&5));
+            assert_eq!(sc.rank(&3), 3);
+            assert_eq!(sc.erase(&9), Some(9));
+            assert_eq!(sc.validate(), Ok(()));
+            let low = sc.split_off(&4);
+            assert_eq!(sc.iter().cloned().collect::<Vec<u32>>(), vec![7, 5]);
+            assert_eq!(low.iter().cloned().collect::<Vec<u32>>(), vec![3, 1]);
+
+            // Strings by length only: equivalent elements are
+            // the ones of the same length
+            type StringOrder = Box<dyn Fn(&String, &String) -> Ordering>;
+            let by_length: StringOrder = Box::new(|a, b| a.len().cmp(&b.len()));
+            let mut sc = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set, by_length);
+            for s in ["ccc", "a", "bb", "dd"].iter() { sc.insert(s.to_string()); }
+            assert_eq!(sc.len(), 3);
+            assert_eq!(sc.get(&"xx".to_string()), Some(&"bb".to_string()));
+            assert_eq!(sc.erase(&"zzz".to_string()), Some("ccc".to_string()));
+            assert_eq!(sc.iter().cloned().collect::<Vec<String>>(), vec!["a", "bb"]);
+
+            // Containers sharing the same comparator type can be combined
+            let descending: fn(&u32, &u32) -> Ordering = |a, b| b.cmp(a);
+            let mut a = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set, descending);
+            let mut b = SortedContainer::with_comparator(Balancing::None, Multiplicity::Set, descending);
+            a.extend(vec![1, 2, 3]);
+            b.extend(vec![4, 3]);
+            assert_eq!(a.union(&b).cloned().collect::<Vec<u32>>(), vec![4, 3, 2, 1]);
+            assert_eq!(a.intersection(&b).cloned().collect::<Vec<u32>>(), vec![3]);
+            a.append(&mut b);
+            assert_eq!(a.iter().cloned().collect::<Vec<u32>>(), vec![4, 3, 2, 1]);
+            assert_eq!(a.validate(), Ok(()));
+
+            // Even with different functions of the same type,
+            // which order the elements differently
+            let ascending: fn(&u32, &u32) -> Ordering = |a, b| a.cmp(b);
+            let mut a = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set, ascending);
+            let mut b = SortedContainer::with_comparator(Balancing::Avl, Multiplicity::Set, descending);
+            a.extend(vec![1, 2, 3]);
+            b.extend(vec![10, 11, 12, 13, 2]);
+            assert_eq!(a.union(&b).cloned().collect::<Vec<u32>>(), vec![1, 2, 3, 10, 11, 12, 13]);
+            assert_eq!(a.intersection(&b).cloned().collect::<Vec<u32>>(), vec![2]);
+            assert_eq!(a.difference(&b).cloned().collect::<Vec<u32>>(), vec![1, 3]);
+            assert_eq!(b.symmetric_difference(&a).cloned().collect::<Vec<u32>>(), vec![13, 12, 11, 10, 3, 1]);
+            assert!(!a.is_subset(&b));
+            b.erase(&2);
+            a.append(&mut b);
+            assert_eq!(a.iter().cloned().collect::<Vec<u32>>(), vec![1, 2, 3, 10, 11, 12, 13]);
+            assert!(a.contains(&11));
+            assert_eq!(a.validate(), Ok(()));
+    }
+
+
+    /// Test exporting the tree in DOT
+    #[test]
+    fn test_to_dot(){
+
+            let mut sc = SortedContainer::with_policy(Balancing::None, Multiplicity::Multiset);
+            assert_eq!(sc.to_dot(), "digraph SortedContainer {\n    ordering=out;\n    node [shape=box];\n    n0 [shape=point];\n}\n");
+
+            for i in [2, 1, 4, 3, 4].iter() {
+                sc.insert(*i);
+            }
+            assert_eq!(sc.to_dot(), concat!(
+                "digraph SortedContainer {\n",
+                "    ordering=out;\n",
+                "    node [shape=box];\n",
+                "    n0 [label=\"2\\nh=3 bf=-1\"];\n",
+                "    n0 -> n1;\n",
+                "    n0 -> n2;\n",
+                "    n1 [label=\"1\\nh=1 bf=0\"];\n",
+                "    n2 [label=\"4 (x2)\\nh=2 bf=1\"];\n",
+                "    n2 -> n3;\n",
+                "    n2 -> n4;\n",
+                "    n3 [label=\"3\\nh=1 bf=0\"];\n",
+                "    n4 [shape=point];\n",
+                "}\n"));
+
+            // The labels are escaped
+            let mut sc = SortedContainer::new();
+            sc.insert("a \"b\" \\c".to_string());
+            assert!(sc.to_dot().contains("n0 [label=\"a \\\"b\\\" \\\\c\\nh=1 bf=0\"];"));
+    }
+
+
+    /// Test drawing the tree with box-drawing characters
+    #[test]
+    fn test_to_ascii(){
+
+            let mut sc = SortedContainer::with_balancing(Balancing::None);
+            assert_eq!(sc.to_ascii(), "(nil)\n");
+
+            for i in [4, 2, 6, 1, 3, 7].iter() {
+                sc.insert(*i);
+            }
+            assert_eq!(sc.to_ascii(), concat!(
+                "4 [h=3 bf=0]\n",
+                "\u{251c}\u{2500}\u{2500} 2 [h=2 bf=0]\n",
+                "\u{2502}   \u{251c}\u{2500}\u{2500} 1 [h=1 bf=0]\n",
+                "\u{2502}   \u{2514}\u{2500}\u{2500} 3 [h=1 bf=0]\n",
+                "\u{2514}\u{2500}\u{2500} 6 [h=2 bf=-1]\n",
+                "    \u{251c}\u{2500}\u{2500} (nil)\n",
+                "    \u{2514}\u{2500}\u{2500} 7 [h=1 bf=0]\n"));
+
+            // The drawing does not depend on the recursion
+            // depth, even for a degenerate tree
+            let mut sc = SortedContainer::with_balancing(Balancing::None);
+            for i in 0..2000 {
+                sc.insert(i);
+            }
+            assert_eq!(sc.to_ascii().lines().count(), 2 * 2000 - 1);
+            assert_eq!(sc.to_dot().lines().count(), 4 + 2000 + 1999 + 2 * 1999);
+            assert_eq!(sc.to_json(|i| i.to_string()).matches("null").count(), 2000 + 1);
+    }
+
+
+    /// Test exporting the tree in JSON
+    #[test]
+    fn test_to_json(){
+
+            let mut sc = SortedContainer::with_policy(Balancing::None, Multiplicity::Multiset);
+            assert_eq!(sc.to_json(|i: &u32| i.to_string()), "null");
+
+            for i in [2, 1, 4, 3, 4].iter() {
+                sc.insert(*i);
+            }
+            assert_eq!(sc.to_json(|i| i.to_string()), concat!(
+                "{\"data\":2,",
+                "\"left\":{\"data\":1,\"left\":null,\"right\":null},",
+                "\"right\":{\"data\":4,\"dups\":[4],",
+                "\"left\":{\"data\":3,\"left\":null,\"right\":null},",
+                "\"right\":null}}"));
+    }
+
 }
@@ -0,0 +1,501 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use sortedcontainer::{self, SortedContainer, Balancing, Cursor, Natural};
+
+
+// An entry of the map: a key and its value.
+// Pairs are ordered according to their key only, so
+// that the map can be stored in a SortedContainer
+struct Pair<K, V> {
+    key:   K,
+    value: V,
+}
+
+
+impl<K: PartialEq, V> PartialEq for Pair<K, V> {
+    fn eq(&self, other: &Pair<K, V>) -> bool {
+        self.key == other.key
+    }
+}
+
+
+impl<K: Eq, V> Eq for Pair<K, V> {}
+
+
+impl<K: Ord, V> PartialOrd for Pair<K, V> {
+    fn partial_cmp(&self, other: &Pair<K, V>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl<K: Ord, V> Ord for Pair<K, V> {
+    fn cmp(&self, other: &Pair<K, V>) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+
+// Compares the key of a pair with the key we are looking for
+fn compare<K, V, Q>(pair: &Pair<K, V>, key: &Q) -> Ordering
+    where K: Borrow<Q>, Q: ?Sized + Ord
+{
+    let k: &Q = pair.key.borrow();
+    k.cmp(key)
+}
+
+
+/// An ordered map, associating a value to each key
+/// It is built on top of a SortedContainer of (key, value)
+/// pairs ordered by key, hence it shares the same tree of
+/// nodes and links.
+/// note: the key type must implement the Ord trait
+pub struct SortedMap<K, V> {
+    pairs: SortedContainer<Pair<K, V>>,
+}
+
+
+impl<K: Ord, V> SortedMap<K, V> {
+
+    /// Creates a new empty sortedmap
+    /// (the underlying tree is kept balanced)
+    pub fn new() -> Self {
+        SortedMap::with_balancing(Balancing::Avl)
+    }
+
+
+    /// Creates a new empty sortedmap using the
+    /// given balancing strategy
+    /// @param balancing: how the tree should keep its shape
+    pub fn with_balancing(balancing: Balancing) -> Self {
+        SortedMap { pairs: SortedContainer::with_balancing(balancing) }
+    }
+
+
+    /// Returns the number of keys in the map
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+
+    /// Returns true if the map has no keys
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+
+    /// Associates a value to a key
+    /// If the key is already present its value is
+    /// replaced (the key itself is not updated)
+    /// @param key: the key
+    /// @param value: the value to associate to the key
+    /// @return the previous value of the key, if any
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => Some(e.insert(value)),
+            Entry::Vacant(e) => {
+                e.insert(value);
+                None
+            }
+        }
+    }
+
+
+    /// Test whether the map contains the given key
+    /// @param key: the key, or any type it can be borrowed as
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.get(key).is_some()
+    }
+
+
+    /// Returns a reference to the value of a key
+    /// @param key: the key, or any type it can be borrowed as
+    /// @return the value if the key was found, None otherwise
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        self.pairs.find_by(|p| compare(p, key)).map(|p| &p.value)
+    }
+
+
+    /// Returns a mutable reference to the value of a key
+    /// @param key: the key, or any type it can be borrowed as
+    /// @return the value if the key was found, None otherwise
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        self.pairs.get_mut_by(|p| compare(p, key)).map(|p| &mut p.value)
+    }
+
+
+    /// Removes a key from the map
+    /// @param key: the key, or any type it can be borrowed as
+    /// @return the value of the removed key, if found
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        self.pairs.erase_by(|p| compare(p, key)).map(|p| p.value)
+    }
+
+
+    /// Gets the entry of the given key, so that it can be
+    /// inspected and modified in place
+    /// e.g. `*map.entry(name).or_insert(0) += 1;`
+    /// note: the key is looked up only once, the entry keeps
+    /// the position where it was found (or should be inserted).
+    /// To do so, the tree is taken apart while the entry lives
+    /// and put back together when it is used or dropped: an
+    /// entry which is leaked (e.g. with mem::forget) leaves the
+    /// map empty, and all its keys and values are leaked too
+    /// @param key: the key
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let cursor = self.pairs.cursor_by(|p| compare(p, &key));
+        if cursor.get().is_some() {
+            Entry::Occupied(OccupiedEntry { cursor })
+        } else {
+            Entry::Vacant(VacantEntry { cursor, key })
+        }
+    }
+
+
+    /// Returns an iterator visiting the (key, value)
+    /// pairs of the map in ascending order of key
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { pairs: self.pairs.iter() }
+    }
+
+
+    /// Returns an iterator visiting the keys in ascending order
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+
+    /// Returns an iterator visiting the values in ascending
+    /// order of their key
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+
+impl<K: Ord, V> Default for SortedMap<K, V> {
+    fn default() -> Self {
+        SortedMap::new()
+    }
+}
+
+
+/// A view into a single key of a SortedMap,
+/// which may either be present or not
+pub enum Entry<'a, K: 'a, V: 'a> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+
+/// An entry whose key is present in the map
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+    cursor: Cursor<'a, Pair<K, V>, Natural>,
+}
+
+
+/// An entry whose key is not present in the map
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+    cursor: Cursor<'a, Pair<K, V>, Natural>,
+    key: K,
+}
+
+
+impl<'a, K: Ord, V> Entry<'a, K, V> {
+
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => e.key(),
+            Entry::Vacant(ref e)   => e.key(),
+        }
+    }
+
+
+    /// Modifies the value in place if the key is present
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(ref mut e) = self {
+            f(e.get_mut());
+        }
+        self
+    }
+
+
+    /// Inserts the given value if the key is not present
+    /// @return the value of the key
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+
+    /// Inserts the value computed by the given function
+    /// if the key is not present
+    /// @return the value of the key
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e)   => e.insert(default()),
+        }
+    }
+}
+
+
+impl<'a, K: Ord, V: Default> Entry<'a, K, V> {
+
+    /// Inserts the default value if the key is not present
+    /// @return the value of the key
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+
+impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
+
+    /// Returns the key of the entry (as stored in the map)
+    pub fn key(&self) -> &K {
+        &self.cursor.get().unwrap().key
+    }
+
+
+    /// Returns a reference to the value of the entry
+    pub fn get(&self) -> &V {
+        &self.cursor.get().unwrap().value
+    }
+
+
+    /// Returns a mutable reference to the value of the entry
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.cursor.get_mut().unwrap().value
+    }
+
+
+    /// Converts the entry into a mutable reference
+    /// to its value, bound to the lifetime of the map
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.cursor.into_mut().unwrap().value
+    }
+
+
+    /// Replaces the value of the entry
+    /// @return the old value
+    pub fn insert(&mut self, value: V) -> V {
+        ::std::mem::replace(self.get_mut(), value)
+    }
+
+
+    /// Removes the entry from the map
+    /// @return the value of the entry
+    pub fn remove(self) -> V {
+        self.cursor.remove().unwrap().value
+    }
+}
+
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+
+    /// Inserts the key into the map with the given value
+    /// @return a mutable reference to the value
+    pub fn insert(self, value: V) -> &'a mut V {
+        let VacantEntry { cursor, key } = self;
+        &mut cursor.insert(Pair { key, value }).value
+    }
+}
+
+
+/// An iterator over the (key, value) pairs of a
+/// SortedMap, visited in ascending order of key
+pub struct Iter<'a, K: 'a, V: 'a> {
+    pairs: sortedcontainer::Iter<'a, Pair<K, V>>,
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.pairs.next().map(|p| (&p.key, &p.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pairs.size_hint()
+    }
+}
+
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.pairs.next_back().map(|p| (&p.key, &p.value))
+    }
+}
+
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+
+impl<'a, K: Ord, V> IntoIterator for &'a SortedMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+
+/********************** TESTS **************************/
+
+
+#[cfg(test)]
+mod tests {
+
+    use sortedmap::{SortedMap, Entry};
+
+
+    /// A basic test of insertion, lookup and removal
+    #[test]
+    fn test_base_api(){
+
+            let mut map : SortedMap<String, u32> = SortedMap::new();
+            assert!(map.is_empty(), "Map not empty");
+
+            assert_eq!(map.insert("bob".to_string(),   30), None);
+            assert_eq!(map.insert("alice".to_string(), 25), None);
+            assert_eq!(map.insert("carol".to_string(), 40), None);
+
+            // Inserting an existing key replaces its value
+            assert_eq!(map.insert("bob".to_string(), 31), Some(30));
+            assert_eq!(map.len(), 3);
+
+            assert_eq!(map.get("bob"), Some(&31));
+            assert_eq!(map.get("dave"), None);
+            assert!(map.contains_key("alice"), "Does not contain alice");
+
+            *map.get_mut("alice").unwrap() += 1;
+            assert_eq!(map.get("alice"), Some(&26));
+            assert_eq!(map.get_mut("dave"), None);
+
+            let keys: Vec<&String> = map.keys().collect();
+            assert_eq!(keys, vec!["alice", "bob", "carol"]);
+            let values: Vec<u32> = map.values().rev().cloned().collect();
+            assert_eq!(values, vec![40, 31, 26]);
+
+            assert_eq!(map.remove("bob"), Some(31));
+            assert_eq!(map.remove("bob"), None);
+            assert_eq!(map.len(), 2);
+
+            let pairs: Vec<(&String, &u32)> = map.iter().collect();
+            assert_eq!(pairs, vec![(&"alice".to_string(), &26), (&"carol".to_string(), &40)]);
+    }
+
+
+    /// Test the entry API
+    #[test]
+    fn test_entry(){
+
+            let mut map : SortedMap<String, u32> = SortedMap::new();
+
+            // Count the occurrences of each word
+            for word in "b a c a b a".split(' ') {
+                *map.entry(word.to_string()).or_insert(0) += 1;
+            }
+            let counts: Vec<(&String, &u32)> = map.iter().collect();
+            assert_eq!(counts, vec![(&"a".to_string(), &3), (&"b".to_string(), &2), (&"c".to_string(), &1)]);
+
+            map.entry("c".to_string()).and_modify(|v| *v *= 10).or_default();
+            map.entry("d".to_string()).and_modify(|v| *v *= 10).or_default();
+            assert_eq!(map.get("c"), Some(&10));
+            assert_eq!(map.get("d"), Some(&0));
+
+            match map.entry("a".to_string()) {
+                Entry::Occupied(mut e) => {
+                    assert_eq!(e.key(), "a");
+                    assert_eq!(*e.get(), 3);
+                    assert_eq!(e.insert(7), 3);
+                    assert_eq!(e.remove(), 7);
+                }
+                Entry::Vacant(_) => panic!("Key a not found"),
+            }
+            assert!(!map.contains_key("a"), "Contains a");
+
+            match map.entry("e".to_string()) {
+                Entry::Occupied(_) => panic!("Key e found"),
+                Entry::Vacant(e) => {
+                    assert_eq!(e.key(), "e");
+                    *e.insert(5) += 1;
+                }
+            }
+            assert_eq!(map.get("e"), Some(&6));
+    }
+
+
+    /// Test that many keys keep the map consistent
+    #[test]
+    fn test_many_keys(){
+
+            let mut map : SortedMap<u32, u32> = SortedMap::new();
+            for i in 0..1000 { map.insert(i, i * i); }
+            for i in (0..1000).filter(|i| i % 3 == 0) { map.remove(&i); }
+
+            for i in 0..1000 {
+                let expected = if i % 3 == 0 { None } else { Some(i * i) };
+                assert_eq!(map.get(&i).cloned(), expected, "Wrong value for {}", i);
+            }
+            assert_eq!(map.len(), 666);
+            assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b), "Keys not sorted");
+    }
+
+
+    /// Test entries of keys which cannot be cloned,
+    /// while the tree is being rebalanced
+    #[test]
+    fn test_entry_positions(){
+
+            #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+            struct Key(u32);
+
+            let mut map : SortedMap<Key, u32> = SortedMap::new();
+            for i in 0..500 {
+                // Zigzag order, so that both sides get rotated
+                let k = if i % 2 == 0 { i / 2 } else { 999 - i / 2 };
+                let value = map.entry(Key(k)).or_insert(0);
+                assert_eq!(*value, 0);
+                *value = k + 1;
+                assert!(map.pairs.validate().is_ok(), "Invalid tree after inserting {}", k);
+            }
+            assert_eq!(map.len(), 500);
+            assert!(map.iter().all(|(k, v)| *v == k.0 + 1), "Wrong values");
+
+            // An entry which is dropped leaves the map as it was
+            for k in [0, 300, 999].iter() {
+                let _ = map.entry(Key(*k));
+            }
+            assert_eq!(map.len(), 500);
+            assert!(map.pairs.validate().is_ok(), "Invalid tree after dropping entries");
+
+            for k in 0..250 {
+                match map.entry(Key(k)) {
+                    Entry::Occupied(e) => assert_eq!(e.remove(), k + 1),
+                    Entry::Vacant(_) => panic!("Key {} not found", k),
+                }
+            }
+            assert!(map.pairs.validate().is_ok(), "Invalid tree after removals");
+            assert_eq!(map.keys().next(), Some(&Key(750)));
+            assert_eq!(*map.entry(Key(999)).or_insert(0), 1000);
+    }
+}
@@ -0,0 +1,117 @@
+// Runs rustsint on the scripts in tests/scripts: each script
+// "<name>.in" must produce the output "<name>.out". A script
+// may start with the comments "# args: <arguments>", giving
+// the command line arguments of rustsint, and "# status: <n>",
+// giving its expected exit status (0 otherwise). An expected
+// line ending with "[...]" only gives the start of the line
+// (e.g. for messages of the operating system)
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+
+
+// Helper function: the value of a header comment of a script
+fn header<'a>(script: &'a str, key: &str) -> Option<&'a str> {
+    let prefix = format!("# {}:", key);
+    script.lines()
+        .find(|line| line.starts_with(&prefix))
+        .map(|line| line[prefix.len()..].trim())
+}
+
+
+// Helper function: the scripts, in alphabetical order
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("scripts");
+    let mut scripts: Vec<PathBuf> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "in"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    scripts
+}
+
+
+// Helper function: whether a line of output is the expected one
+fn matches(line: &str, expected: &str) -> bool {
+    match expected.strip_suffix("[...]") {
+        Some(start) => line.starts_with(start),
+        None => line == expected,
+    }
+}
+
+
+// Helper function: checks the output of rustsint on a script
+fn check(path: &Path, output: Output) {
+    let script = fs::read_to_string(path).unwrap();
+    let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+    let status: i32 = header(&script, "status").map_or(0, |status| status.parse().unwrap());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let same = stdout.lines().count() == expected.lines().count() &&
+        stdout.lines().zip(expected.lines()).all(|(line, expected)| matches(line, expected));
+    assert!(same, "{}:\n{}\nexpected:\n{}", path.display(), stdout, expected);
+    assert_eq!(output.status.code(), Some(status), "{}", path.display());
+}
+
+
+// Helper function: the arguments of rustsint for a script
+fn args(path: &Path) -> Vec<String> {
+    let script = fs::read_to_string(path).unwrap();
+    header(&script, "args").map_or(Vec::new(), |args| args.split_whitespace().map(String::from).collect())
+}
+
+
+/// Test the scripts, given with --script
+#[test]
+fn test_scripts(){
+
+        for path in scripts() {
+            let output = Command::new(env!("CARGO_BIN_EXE_rustsint"))
+                .args(args(&path))
+                .arg("--script").arg(&path)
+                .output().unwrap();
+            check(&path, output);
+        }
+}
+
+
+/// Test the scripts, piped into stdin
+#[test]
+fn test_piped_scripts(){
+
+        for path in scripts() {
+            let mut child = Command::new(env!("CARGO_BIN_EXE_rustsint"))
+                .args(args(&path))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn().unwrap();
+            child.stdin.take().unwrap().write_all(&fs::read(&path).unwrap()).unwrap();
+            check(&path, child.wait_with_output().unwrap());
+        }
+}
+
+
+/// Test exporting the tree with dot, to a temporary file
+#[test]
+fn test_dot_file(){
+
+        let file = env::temp_dir().join(format!("rustsint-dot-{}.dot", process::id()));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rustsint"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn().unwrap();
+        let script = format!("i 30 bob\ni 25 al\ndot {}\nx\n", file.display());
+        child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "exported 2 record(s)\nExiting...\n");
+        assert_eq!(output.status.code(), Some(0));
+
+        let dot = fs::read_to_string(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert!(dot.starts_with("digraph SortedContainer {\n"));
+        assert!(dot.contains("[label=\"Age: 30, Name: bob\\nh=2 bf=1\"];"));
+        assert!(dot.ends_with("}\n"));
+}